@@ -0,0 +1,56 @@
+// Image files as the world background. The file is decoded once and fitted to the world, either stretched over
+// it or repeated from the top left corner; saved scenes keep the path and the fit and read the file again.
+
+use image::{imageops::FilterType, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BackgroundFit {
+    Scaled,
+    Tiled,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackgroundImage {
+    pub path: String,
+    pub fit: BackgroundFit,
+}
+
+impl BackgroundImage {
+    // rgb values row by row for a world of the given size; PNG, JPEG and BMP files are recognised by content
+    pub fn load(&self, width: usize, height: usize) -> Result<Vec<u8>, SimulationError> {
+        let image: RgbImage = image::open(&self.path)
+            .map_err(|error| SimulationError::BackgroundImage {
+                path: self.path.clone(),
+                message: error.to_string(),
+            })?
+            .to_rgb8();
+
+        if image.width() == 0 || image.height() == 0 {
+            return Err(SimulationError::BackgroundImage {
+                path: self.path.clone(),
+                message: String::from("the image is empty"),
+            });
+        }
+
+        return match self.fit {
+            BackgroundFit::Scaled => {
+                Ok(image::imageops::resize(&image, width as u32, height as u32, FilterType::Triangle).into_raw())
+            }
+            BackgroundFit::Tiled => {
+                let mut data: Vec<u8> = Vec::with_capacity(width * height * 3);
+
+                for j in 0..height {
+                    for i in 0..width {
+                        let pixel = image.get_pixel(i as u32 % image.width(), j as u32 % image.height());
+                        data.extend_from_slice(&pixel.0);
+                    }
+                }
+
+                Ok(data)
+            }
+        };
+    }
+}
@@ -0,0 +1,52 @@
+// Uniform grid used as a broadphase: finds circles that may be close to a point without checking all of them
+
+pub struct UniformGrid {
+    pub cell_size: f64,
+    pub columns: usize,
+    pub rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl UniformGrid {
+    pub fn new(width: f64, height: f64, cell_size: f64) -> UniformGrid {
+        let columns: usize = f64::max((width / cell_size).ceil(), 1.0) as usize;
+        let rows: usize = f64::max((height / cell_size).ceil(), 1.0) as usize;
+
+        return UniformGrid {
+            cell_size,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+        };
+    }
+
+    // anything outside of the world is put into the nearest border cell
+    pub fn cell_of(&self, x: f64, y: f64) -> (usize, usize) {
+        let column: f64 = f64::clamp((x / self.cell_size).floor(), 0.0, (self.columns - 1) as f64);
+        let row: f64 = f64::clamp((y / self.cell_size).floor(), 0.0, (self.rows - 1) as f64);
+
+        return (column as usize, row as usize);
+    }
+
+    pub fn insert(&mut self, index: usize, x: f64, y: f64) {
+        let (column, row) = self.cell_of(x, y);
+
+        self.cells[row * self.columns + column].push(index);
+    }
+
+    // returns every index stored in the cells overlapping the square of half-size `range` around (x, y)
+    pub fn query(&self, x: f64, y: f64, range: f64) -> Vec<usize> {
+        let (column_start, row_start) = self.cell_of(x - range, y - range);
+        let (column_end, row_end) = self.cell_of(x + range, y + range);
+
+        let mut found: Vec<usize> = Vec::new();
+
+        for row in row_start..(row_end + 1) {
+            for column in column_start..(column_end + 1) {
+                found.extend_from_slice(&self.cells[row * self.columns + column]);
+            }
+        }
+
+        return found;
+    }
+}
@@ -1,9 +1,26 @@
 // Circle struct with all its implementations
 
-use crate::common_structs::{RGBColor, RGBCanvas};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+use crate::{common_structs::{BlendMode, RGBAColor, RGBColor, RGBCanvas}, drawing::Canvas, events::CollisionEvent, svg, text};
+
+const LABEL_COLOR: RGBColor = RGBColor { r: 255, g: 255, b: 255 };
+const LABEL_SHADOW_COLOR: RGBColor = RGBColor { r: 0, g: 0, b: 0 };
+
+// what is written next to every circle
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum CircleLabel {
+    #[default]
+    Off,
+    Name,
+    Mass,
+    Speed,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Circle {
+    pub id: usize,
+    pub cluster_id: Option<usize>,
     pub name: String,
     pub x_pos: f64,
     pub y_pos: f64,
@@ -14,9 +31,26 @@ pub struct Circle {
     body_color: RGBColor,
     border_color: RGBColor,
     pub mass: f64,
+    pub is_fluid: bool, // fluid particles interact with each other through SPH forces instead of collisions
+    #[serde(default = "full_opacity")]
+    pub opacity: f64, // from 0.0 (invisible) to 1.0 (opaque)
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    #[serde(default = "leaves_trail")]
+    pub leaves_trail: bool, // drawn when trails are shown at all
+    #[serde(skip)]
     is_selected: bool,
 }
 
+// circles saved before opacity existed are opaque
+fn full_opacity() -> f64 {
+    return 1.0;
+}
+
+fn leaves_trail() -> bool {
+    return true;
+}
+
 impl Circle {
     pub fn new(
         name: String,
@@ -35,6 +69,8 @@ impl Circle {
         }
 
         return Circle {
+            id: 0,
+            cluster_id: None,
             name,
             x_pos,
             y_pos,
@@ -45,12 +81,18 @@ impl Circle {
             body_color,
             border_color,
             mass,
+            is_fluid: false,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            leaves_trail: true,
             is_selected: false,
         }
     }
 
     pub fn clone(&self) -> Circle {
         return Circle {
+            id: self.id,
+            cluster_id: self.cluster_id,
             name: String::from(&self.name),
             x_pos: self.x_pos,
             y_pos: self.y_pos,
@@ -61,6 +103,10 @@ impl Circle {
             body_color: self.body_color,
             border_color: self.border_color,
             mass: self.mass,
+            is_fluid: self.is_fluid,
+            opacity: self.opacity,
+            blend_mode: self.blend_mode,
+            leaves_trail: self.leaves_trail,
             is_selected: false,
         }
     }
@@ -111,7 +157,10 @@ impl Circle {
         }
     }
 
-    pub fn collide_with_other_circles(&mut self, circles_array: &Vec<Circle>, own_index: usize) {
+    // elastic collision with every touching circle; each circle of a pair computes its own response from the same
+    // pre-collision copy, so both sides agree on the time of impact and the exchanged momentum.
+    // Returns the collisions where this circle has the smaller id, so that every pair is reported once.
+    pub fn collide_with_other_circles(&mut self, circles_array: &Vec<Circle>, own_index: usize) -> Vec<CollisionEvent> {
         let mut distance_squared: f64;
         let mut sum_radii_squared: f64;
 
@@ -121,23 +170,21 @@ impl Circle {
         let mut rel_y_t: f64;
         let mut rel_v_x: f64;
         let mut rel_v_y: f64;
-        let mut abs_rel_vel: f64;
         let mut a: f64;
         let mut b: f64;
         let mut c: f64;
         let mut t: f64;
-        let mut t1: f64;
-        let mut t2: f64;
         let mut sin_phi: f64;
         let mut cos_phi: f64;
-        let mut new_v_x: f64;
-        let mut new_v_y: f64;
+        let mut normal_rel_vel: f64;
+        let mut new_x_vel: f64;
+        let mut new_y_vel: f64;
 
-        let old_m: f64 = self.mass * f64::sqrt(self.x_vel * self.x_vel + self.y_vel * self.y_vel);
-        let mut new_m: f64;
+        let mut events: Vec<CollisionEvent> = Vec::new();
 
         for i in 0..circles_array.len() {
-            if i != own_index {
+            // contacts with clustered circles and fluid particles are resolved by the State as rigid body impulses
+            if i != own_index && circles_array[i].cluster_id.is_none() && !circles_array[i].is_fluid {
                 distance_squared = (circles_array[i].x_pos - self.x_pos) * (circles_array[i].x_pos - self.x_pos) + (circles_array[i].y_pos - self.y_pos) * (circles_array[i].y_pos - self.y_pos);
 
                 sum_radii_squared = (circles_array[i].radius +  self.radius) * (circles_array[i].radius +  self.radius);
@@ -148,57 +195,67 @@ impl Circle {
                     rel_v_x = circles_array[i].x_vel - self.x_vel;
                     rel_v_y = circles_array[i].y_vel - self.y_vel;
 
-                    // magnitude of collider's relative velocity
-                    abs_rel_vel = f64::sqrt(rel_v_x * rel_v_x + rel_v_y * rel_v_y);
-
-                    // relative positions of collider at t0 and t1
+                    // relative position of collider at the end of the step
                     rel_x = circles_array[i].x_pos - self.x_pos;
                     rel_y = circles_array[i].y_pos - self.y_pos;
- 
-                    if abs_rel_vel != 0.0 {
-                        // coefficients for calculating the precize time of collision
-                        a = abs_rel_vel;
-                        b = 2.0 * (rel_v_x * rel_x + rel_v_y * rel_y);
-                        c = rel_x * rel_x + rel_y * rel_y - sum_radii_squared;
 
-                        t1 = (-b + f64::sqrt(b * b - 4.0 * a * c))/(2.0 * a);
-                        t2 = (-b - f64::sqrt(b * b - 4.0 * a * c))/(2.0 * a);
+                    // circles that are already moving apart are left alone
+                    if rel_x * rel_v_x + rel_y * rel_v_y >= 0.0 {
+                        continue;
+                    }
 
-                        t = t2;
+                    // time t back from the end of the step when the circles were just touching:
+                    // |rel - t * rel_v|^2 = sum_radii^2, the only positive root since c < 0
+                    a = rel_v_x * rel_v_x + rel_v_y * rel_v_y;
+                    b = 2.0 * (rel_v_x * rel_x + rel_v_y * rel_y);
+                    c = rel_x * rel_x + rel_y * rel_y - sum_radii_squared;
 
-                        // relative position at impact
-                        rel_x_t = rel_x + t * rel_v_x;
-                        rel_y_t = rel_y + t * rel_v_y;
+                    t = (b + f64::sqrt(b * b - 4.0 * a * c)) / (2.0 * a);
 
-                        sin_phi = rel_y_t / f64::sqrt(sum_radii_squared);
-                        cos_phi = rel_x_t / f64::sqrt(sum_radii_squared);
-    
-                        new_v_x = cos_phi * (rel_v_x * cos_phi + rel_v_y * sin_phi);
-                        new_v_y = sin_phi * (rel_v_x * cos_phi + rel_v_y * sin_phi);
-    
-                        if t > -1.0 {
-                            self.x_pos = self.x_pos + self.x_vel * t - new_v_x * t;
-                            self.y_pos = self.y_pos + self.y_vel * t - new_v_y * t;
-
-                            self.x_vel += new_v_x * circles_array[i].mass / self.mass;
-                            self.y_vel += new_v_y * circles_array[i].mass / self.mass;
-                        } else {
-                            self.x_pos = self.x_pos - self.x_vel;
-                            self.y_pos = self.y_pos - self.y_vel;
+                    // overlap older than one step (e.g. circles placed overlapping), only fix the velocities
+                    if t > 1.0 {
+                        t = 0.0;
+                    }
 
-                            self.x_vel += new_v_x * circles_array[i].mass / self.mass;
-                            self.y_vel += new_v_y * circles_array[i].mass / self.mass;
-                        }
+                    // relative position at impact
+                    rel_x_t = rel_x - t * rel_v_x;
+                    rel_y_t = rel_y - t * rel_v_y;
+
+                    sin_phi = rel_y_t / f64::sqrt(rel_x_t * rel_x_t + rel_y_t * rel_y_t);
+                    cos_phi = rel_x_t / f64::sqrt(rel_x_t * rel_x_t + rel_y_t * rel_y_t);
+
+                    normal_rel_vel = rel_v_x * cos_phi + rel_v_y * sin_phi;
+
+                    new_x_vel = self.x_vel + 2.0 * circles_array[i].mass / (self.mass + circles_array[i].mass) * normal_rel_vel * cos_phi;
+                    new_y_vel = self.y_vel + 2.0 * circles_array[i].mass / (self.mass + circles_array[i].mass) * normal_rel_vel * sin_phi;
+
+                    if self.id < circles_array[i].id {
+                        events.push(CollisionEvent {
+                            global_time: 0, // filled in by the State
+                            circle_a: self.id,
+                            circle_b: circles_array[i].id,
+                            contact_x: self.x_pos - self.x_vel * t + self.radius * cos_phi,
+                            contact_y: self.y_pos - self.y_vel * t + self.radius * sin_phi,
+                            normal_x: cos_phi,
+                            normal_y: sin_phi,
+                            relative_speed: -normal_rel_vel,
+                            impulse: -2.0 * self.mass * circles_array[i].mass / (self.mass + circles_array[i].mass) * normal_rel_vel,
+                            time_in_step: 1.0 - t,
+                            is_sticky: false,
+                        });
+                    }
 
-                        new_m = self.mass * f64::sqrt(self.x_vel * self.x_vel + self.y_vel * self.y_vel);
+                    // go back to the moment of impact and spend the rest of the step with the new velocity
+                    self.x_pos = self.x_pos - self.x_vel * t + new_x_vel * t;
+                    self.y_pos = self.y_pos - self.y_vel * t + new_y_vel * t;
 
-                        if t < -1.0 {
-                            println!("#{:>3} -> #{:>3}: t= {:>8.3}; old_m= {:>5.3}; new_m= {:>5.3}", own_index + 1, i + 1, t, old_m, new_m);
-                        }
-                    }
+                    self.x_vel = new_x_vel;
+                    self.y_vel = new_y_vel;
                 }
             }
         }
+
+        return events;
     }
 
 
@@ -233,20 +290,91 @@ impl Circle {
         self.y_pos = new_y;
     }
 
+    pub fn body_color(&self) -> RGBColor {
+        return self.body_color;
+    }
+
+    // copy with positions and sizes multiplied by the factor, for drawing at a higher resolution
+    pub fn scaled(&self, factor: f64) -> Circle {
+        let mut circle: Circle = self.clone();
+
+        circle.x_pos *= factor;
+        circle.y_pos *= factor;
+        circle.radius *= factor;
+        circle.border_width *= factor;
+
+        return circle;
+    }
+
+    // the border lies inside the radius, as on the canvas
+    pub fn to_svg(&self) -> String {
+        let stroke: String = if self.border_width > 0.0 {
+            format!(" stroke=\"{}\" stroke-width=\"{}\"", svg::color(self.border_color), svg::number(self.border_width))
+        } else {
+            String::new()
+        };
+
+        let opacity: String = if self.opacity < 1.0 {format!(" opacity=\"{}\"", svg::number(self.opacity))} else {String::new()};
+
+        let blend: &str = match self.blend_mode {
+            BlendMode::Normal => "",
+            BlendMode::Additive => " style=\"mix-blend-mode:plus-lighter\"",
+            BlendMode::Multiply => " style=\"mix-blend-mode:multiply\"",
+        };
+
+        return format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"{}{}{}/>",
+            svg::number(self.x_pos),
+            svg::number(self.y_pos),
+            svg::number(self.radius - self.border_width / 2.0),
+            svg::color(self.body_color),
+            stroke,
+            opacity,
+            blend,
+        );
+    }
+
+    pub fn label_text(&self, label: CircleLabel) -> Option<String> {
+        return match label {
+            CircleLabel::Off => None,
+            CircleLabel::Name => Some(self.name.clone()),
+            CircleLabel::Mass => Some(format!("m {:.1}", self.mass)),
+            CircleLabel::Speed => Some(format!("v {:.2}", self.x_vel.hypot(self.y_vel))),
+        };
+    }
+
+    // right of the circle, vertically centred on it
+    pub fn put_label_on_canvas(&self, canvas: &mut RGBCanvas, label: CircleLabel, scale: usize) {
+        if let Some(label_text) = self.label_text(label) {
+            let (_, height) = text::text_size(&label_text, scale);
+
+            canvas.draw_text_with_shadow(
+                (self.x_pos + self.radius) as i32 + 2 * scale as i32,
+                self.y_pos as i32 - height as i32 / 2,
+                &label_text,
+                LABEL_COLOR,
+                LABEL_SHADOW_COLOR,
+                scale,
+            );
+        }
+    }
+
+    // the body and border are composited with the circle's opacity and blend mode, so that overlapping
+    // translucent circles show each other
     pub fn put_on_canvas_smoothed(&self, canvas: &mut RGBCanvas) {
         if self.x_pos >= 0.0 - self.radius
-        && self.x_pos < canvas.width + self.radius
+        && self.x_pos < canvas.width as f64 + self.radius
         && self.y_pos >= 0.0 - self.radius
-        && self.y_pos < canvas.height + self.radius
+        && self.y_pos < canvas.height as f64 + self.radius
+        && self.opacity > 0.0
         {
-            let width: i32 = canvas.width as i32;
-            // let x: i32 = self.x_pos as i32;
-            // let y: i32 = self.y_pos as i32;
             let box_lx: i32 = if self.x_pos > self.radius {(self.x_pos - self.radius) as i32} else {0};
             let box_hx: i32 = (self.x_pos + self.radius + 2.0) as i32;
             let box_ly: i32 = if self.y_pos > self.radius {(self.y_pos - self.radius) as i32} else {0};
             let box_hy: i32 = (self.y_pos + self.radius + 2.0) as i32;
 
+            let alpha: u8 = (self.opacity.min(1.0) * 255.0).round() as u8;
+
             let mut distance: f64;
 
             let inner_radius: f64 = self.radius - self.border_width;
@@ -258,34 +386,38 @@ impl Circle {
 
             for j in box_ly..box_hy {
                 for i in box_lx..box_hx {
-                    if i >= 0 && i < canvas.width as i32&& j >= 0 && j < canvas.height as i32 {
-                        x_f = i as f64;
-                        y_f = j as f64;
-
-                        distance = f64::sqrt((self.x_pos - x_f) * (self.x_pos - x_f) + (self.y_pos - y_f) * (self.y_pos - y_f));
-
-                        if distance <= inner_radius {
-                            canvas.data[(width * j * 3 + i * 3 + 0) as usize] = self.body_color.r;
-                            canvas.data[(width * j * 3 + i * 3 + 1) as usize] = self.body_color.g;
-                            canvas.data[(width * j * 3 + i * 3 + 2) as usize] = self.body_color.b;
-                        } else if distance <= inner_radius + 1.0 {
-                            d = distance - inner_radius;
-                            canvas.data[(width * j * 3 + i * 3 + 0) as usize] = ((self.body_color.r as f64) * (1.0 - d) + (self.border_color.r as f64) * d) as u8;
-                            canvas.data[(width * j * 3 + i * 3 + 1) as usize] = ((self.body_color.g as f64) * (1.0 - d) + (self.border_color.g as f64) * d) as u8;
-                            canvas.data[(width * j * 3 + i * 3 + 2) as usize] = ((self.body_color.b as f64) * (1.0 - d) + (self.border_color.b as f64) * d) as u8;
-                        } else if distance <= self.radius {
-                            canvas.data[(width * j * 3 + i * 3 + 0) as usize] = self.border_color.r;
-                            canvas.data[(width * j * 3 + i * 3 + 1) as usize] = self.border_color.g;
-                            canvas.data[(width * j * 3 + i * 3 + 2) as usize] = self.border_color.b;
-                        } else if distance <= self.radius + 1.0 {
-                            d = distance - self.radius;
-                            canvas.data[(width * j * 3 + i * 3 + 0) as usize] = ((self.border_color.r as f64) * (1.0 - d) + (canvas.data[(width * j * 3 + i * 3 + 0) as usize] as f64) * d) as u8;
-                            canvas.data[(width * j * 3 + i * 3 + 1) as usize] = ((self.border_color.g as f64) * (1.0 - d) + (canvas.data[(width * j * 3 + i * 3 + 1) as usize] as f64) * d) as u8;
-                            canvas.data[(width * j * 3 + i * 3 + 2) as usize] = ((self.border_color.b as f64) * (1.0 - d) + (canvas.data[(width * j * 3 + i * 3 + 2) as usize] as f64) * d) as u8;
-                        }
-                    }
+                    x_f = i as f64;
+                    y_f = j as f64;
+
+                    distance = f64::sqrt((self.x_pos - x_f) * (self.x_pos - x_f) + (self.y_pos - y_f) * (self.y_pos - y_f));
+
+                    // colour of the circle at this pixel and how much of the pixel it covers
+                    let (color, coverage): (RGBColor, f64) = if distance <= inner_radius {
+                        (self.body_color, 1.0)
+                    } else if distance <= inner_radius + 1.0 {
+                        d = distance - inner_radius;
+                        (mix_colors(self.body_color, self.border_color, d), 1.0)
+                    } else if distance <= self.radius {
+                        (self.border_color, 1.0)
+                    } else if distance <= self.radius + 1.0 {
+                        d = distance - self.radius;
+                        (self.border_color, 1.0 - d)
+                    } else {
+                        continue;
+                    };
+
+                    canvas.composite_pixel(i, j, RGBAColor::from_rgb(color, alpha), coverage, self.blend_mode);
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// from the first colour at 0.0 to the second at 1.0
+fn mix_colors(first: RGBColor, second: RGBColor, d: f64) -> RGBColor {
+    return RGBColor {
+        r: ((first.r as f64) * (1.0 - d) + (second.r as f64) * d) as u8,
+        g: ((first.g as f64) * (1.0 - d) + (second.g as f64) * d) as u8,
+        b: ((first.b as f64) * (1.0 - d) + (second.b as f64) * d) as u8,
+    };
+}
@@ -0,0 +1,207 @@
+// Cluster struct: a group of circles stuck together and moving as one rigid body
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterMember {
+    pub circle_id: usize,
+    pub offset_x: f64, // position relative to the centre of mass when the cluster angle is zero
+    pub offset_y: f64,
+    pub radius: f64,
+    pub mass: f64,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cluster {
+    pub id: usize,
+    pub members: Vec<ClusterMember>,
+    pub x_pos: f64, // centre of mass
+    pub y_pos: f64,
+    pub x_vel: f64,
+    pub y_vel: f64,
+    pub angle: f64,
+    pub angular_vel: f64,
+    pub mass: f64,
+    pub moment_of_inertia: f64,
+    pub is_fixed: bool,
+}
+
+impl Cluster {
+    // members are given as (circle_id, x, y, radius, mass) in world coordinates,
+    // angular momentum is taken about the world origin, so that it can simply be summed over merged bodies
+    pub fn from_members(
+        id: usize,
+        members: &Vec<(usize, f64, f64, f64, f64)>,
+        momentum_x: f64,
+        momentum_y: f64,
+        angular_momentum: f64,
+        is_fixed: bool,
+    ) -> Cluster {
+        let mut mass: f64 = 0.0;
+        let mut x_pos: f64 = 0.0;
+        let mut y_pos: f64 = 0.0;
+
+        for &(_, x, y, _, m) in members.iter() {
+            mass += m;
+            x_pos += x * m;
+            y_pos += y * m;
+        }
+
+        x_pos = x_pos / mass;
+        y_pos = y_pos / mass;
+
+        let mut moment_of_inertia: f64 = 0.0;
+        let mut cluster_members: Vec<ClusterMember> = Vec::with_capacity(members.len());
+
+        for &(circle_id, x, y, radius, m) in members.iter() {
+            // solid disc about its own centre plus parallel axis term
+            moment_of_inertia += m * radius * radius / 2.0 + m * ((x - x_pos) * (x - x_pos) + (y - y_pos) * (y - y_pos));
+
+            cluster_members.push(ClusterMember {
+                circle_id,
+                offset_x: x - x_pos,
+                offset_y: y - y_pos,
+                radius,
+                mass: m,
+            });
+        }
+
+        let mut x_vel: f64 = 0.0;
+        let mut y_vel: f64 = 0.0;
+        let mut angular_vel: f64 = 0.0;
+
+        if !is_fixed {
+            x_vel = momentum_x / mass;
+            y_vel = momentum_y / mass;
+
+            // move the angular momentum from the world origin to the centre of mass
+            let own_angular_momentum: f64 = angular_momentum - (x_pos * momentum_y - y_pos * momentum_x);
+
+            angular_vel = own_angular_momentum / moment_of_inertia;
+        }
+
+        return Cluster {
+            id,
+            members: cluster_members,
+            x_pos,
+            y_pos,
+            x_vel,
+            y_vel,
+            angle: 0.0,
+            angular_vel,
+            mass,
+            moment_of_inertia,
+            is_fixed,
+        };
+    }
+
+    pub fn contains(&self, circle_id: usize) -> bool {
+        return self.members.iter().any(|member| member.circle_id == circle_id);
+    }
+
+    pub fn member_position(&self, member: &ClusterMember) -> (f64, f64) {
+        let (sin_a, cos_a) = self.angle.sin_cos();
+
+        return (
+            self.x_pos + member.offset_x * cos_a - member.offset_y * sin_a,
+            self.y_pos + member.offset_x * sin_a + member.offset_y * cos_a,
+        );
+    }
+
+    pub fn velocity_at(&self, x: f64, y: f64) -> (f64, f64) {
+        return (
+            self.x_vel - self.angular_vel * (y - self.y_pos),
+            self.y_vel + self.angular_vel * (x - self.x_pos),
+        );
+    }
+
+    pub fn linear_momentum(&self) -> (f64, f64) {
+        if self.is_fixed {
+            return (0.0, 0.0);
+        }
+
+        return (self.mass * self.x_vel, self.mass * self.y_vel);
+    }
+
+    // angular momentum about the world origin
+    pub fn angular_momentum(&self) -> f64 {
+        if self.is_fixed {
+            return 0.0;
+        }
+
+        return self.moment_of_inertia * self.angular_vel
+            + self.mass * (self.x_pos * self.y_vel - self.y_pos * self.x_vel);
+    }
+
+    pub fn inverse_mass(&self) -> f64 {
+        return if self.is_fixed {0.0} else {1.0 / self.mass};
+    }
+
+    pub fn inverse_moment_of_inertia(&self) -> f64 {
+        return if self.is_fixed {0.0} else {1.0 / self.moment_of_inertia};
+    }
+
+    pub fn apply_impulse(&mut self, x: f64, y: f64, impulse_x: f64, impulse_y: f64) {
+        if !self.is_fixed {
+            self.x_vel += impulse_x / self.mass;
+            self.y_vel += impulse_y / self.mass;
+            self.angular_vel += ((x - self.x_pos) * impulse_y - (y - self.y_pos) * impulse_x) / self.moment_of_inertia;
+        }
+    }
+
+    pub fn translate(&mut self, delta_x: f64, delta_y: f64) {
+        self.x_pos += delta_x;
+        self.y_pos += delta_y;
+    }
+
+    pub fn advance(&mut self) {
+        if !self.is_fixed {
+            self.x_pos += self.x_vel;
+            self.y_pos += self.y_vel;
+            self.angle += self.angular_vel;
+        }
+    }
+
+    pub fn bounce_off_walls(&mut self, x_range_start: f64, x_range_end: f64, y_range_start: f64, y_range_end: f64) {
+        if self.is_fixed {
+            return;
+        }
+
+        for i in 0..self.members.len() {
+            let (x, y) = self.member_position(&self.members[i]);
+            let radius: f64 = self.members[i].radius;
+
+            // (penetration depth, normal pointing back into the world)
+            let walls: [(f64, f64, f64); 4] = [
+                (x_range_start + radius - x, 1.0, 0.0),
+                (x + radius - x_range_end, -1.0, 0.0),
+                (y_range_start + radius - y, 0.0, 1.0),
+                (y + radius - y_range_end, 0.0, -1.0),
+            ];
+
+            for &(depth, n_x, n_y) in walls.iter() {
+                if depth > 0.0 {
+                    let contact_x: f64 = x - n_x * radius;
+                    let contact_y: f64 = y - n_y * radius;
+
+                    self.translate(n_x * depth, n_y * depth);
+                    self.collide_with_wall(contact_x, contact_y, n_x, n_y);
+                }
+            }
+        }
+    }
+
+    fn collide_with_wall(&mut self, contact_x: f64, contact_y: f64, n_x: f64, n_y: f64) {
+        let (v_x, v_y) = self.velocity_at(contact_x, contact_y);
+        let v_n: f64 = v_x * n_x + v_y * n_y;
+
+        if v_n < 0.0 {
+            let r_cross_n: f64 = (contact_x - self.x_pos) * n_y - (contact_y - self.y_pos) * n_x;
+
+            // perfectly elastic, same as the walls for single circles
+            let impulse: f64 = -2.0 * v_n / (1.0 / self.mass + r_cross_n * r_cross_n / self.moment_of_inertia);
+
+            self.apply_impulse(contact_x, contact_y, impulse * n_x, impulse * n_y);
+        }
+    }
+}
@@ -1,36 +1,76 @@
-#[derive(Copy, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RGBColor {
     pub r: u8,
     pub g: u8,
     pub b: u8,
 }
 
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RGBAColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8, // 0 is fully transparent
+}
+
+impl RGBAColor {
+    pub fn from_rgb(color: RGBColor, a: u8) -> RGBAColor {
+        return RGBAColor {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+            a,
+        };
+    }
+}
+
+// how a colour is combined with what is already on the canvas, before its alpha is applied
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Additive, // lightens, overlapping circles add up towards white
+    Multiply, // darkens, as overlapping filters do
+}
+
+#[derive(Clone)]
 pub struct RGBCanvas {
-    pub width: f64,
-    pub height: f64,
-    pub data: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>, // rgb values row by row
 }
 
 impl RGBCanvas {
-    pub fn new(width: f64, height: f64) -> RGBCanvas {
+    pub fn new(width: usize, height: usize) -> RGBCanvas {
         return RGBCanvas {
             width,
             height,
-            data: vec![0; (width * height * 3.0) as usize],
+            data: vec![0; width * height * 3],
         };
     }
 
     pub fn copy(&self) -> RGBCanvas {
-        let mut copied_data: Vec<u8> = vec![0; self.data.len()];
+        return self.clone();
+    }
+}
 
-        for i in 0..self.data.len() {
-            copied_data[i] = self.data[i];
-        }
+// layer with transparency, composited onto an RGBCanvas or flattened before it is shown
+#[derive(Clone)]
+pub struct RGBACanvas {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>, // rgba values row by row, not premultiplied
+}
 
-        return RGBCanvas {
-            width: self.width,
-            height: self.height,
-            data: copied_data,
+impl RGBACanvas {
+    // fully transparent
+    pub fn new(width: usize, height: usize) -> RGBACanvas {
+        return RGBACanvas {
+            width,
+            height,
+            data: vec![0; width * height * 4],
         };
     }
 }
@@ -56,4 +96,4 @@ impl Coord {
         self.x = x as f64;
         self.y = y as f64;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,257 @@
+// Debug layer drawn over the rendered view while tuning the physics; every category is switched on its own
+// from View/Debug. It is part of the view, so exported images show it as well; videos are captured without it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    broadphase::UniformGrid,
+    common_structs::{BlendMode, RGBAColor, RGBCanvas, RGBColor},
+    drawing::{Canvas, LineStyle},
+    events::CollisionEvent,
+    state::State,
+};
+
+const VELOCITY_COLOR: RGBColor = RGBColor { r: 255, g: 80, b: 80 };
+const VELOCITY_SCALE: f64 = 10.0; // arrow length per unit of speed, as in the SVG export
+const CONTACT_COLOR: RGBColor = RGBColor { r: 255, g: 220, b: 0 };
+const CONTACT_NORMAL_LENGTH: f64 = 12.0;
+const CONTACT_STEPS_SHOWN: usize = 15; // a contact lasts a single step, far too short to be seen
+const BOUNDING_BOX_COLOR: RGBColor = RGBColor { r: 80, g: 220, b: 120 };
+const GRID_COLOR: RGBColor = RGBColor { r: 110, g: 110, b: 110 };
+const OCCUPIED_CELL_COLOR: RGBAColor = RGBAColor { r: 90, g: 160, b: 255, a: 50 };
+const CENTRE_OF_MASS_COLOR: RGBColor = RGBColor { r: 255, g: 0, b: 255 };
+const TIME_OF_IMPACT_COLOR: RGBColor = RGBColor { r: 255, g: 160, b: 0 };
+const TIME_OF_IMPACT_HORIZON: f64 = 60.0; // steps looked ahead
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum DebugLayer {
+    Velocities,
+    Contacts,
+    BoundingBoxes,
+    BroadphaseGrid,
+    CentreOfMass,
+    TimeOfImpact,
+}
+
+#[derive(Default)]
+pub struct DebugOverlay {
+    pub velocities: bool,
+    pub contacts: bool, // contact points with their normals
+    pub bounding_boxes: bool, // of circles, and dashed of whole clusters
+    pub broadphase_grid: bool, // cells of the collision grid, occupied ones tinted
+    pub centre_of_mass: bool, // of everything, and of every cluster
+    pub time_of_impact: bool, // where free circles will touch if they keep going straight
+    recent_contacts: Vec<CollisionEvent>,
+}
+
+impl DebugOverlay {
+    pub fn toggle(&mut self, layer: DebugLayer) {
+        let enabled: &mut bool = match layer {
+            DebugLayer::Velocities => &mut self.velocities,
+            DebugLayer::Contacts => &mut self.contacts,
+            DebugLayer::BoundingBoxes => &mut self.bounding_boxes,
+            DebugLayer::BroadphaseGrid => &mut self.broadphase_grid,
+            DebugLayer::CentreOfMass => &mut self.centre_of_mass,
+            DebugLayer::TimeOfImpact => &mut self.time_of_impact,
+        };
+
+        *enabled = !*enabled;
+    }
+
+    // call after every step with its collisions; contacts from the future are dropped when history is rewound
+    pub fn remember_contacts(&mut self, collisions: &[CollisionEvent], global_time: usize) {
+        self.recent_contacts.extend_from_slice(collisions);
+        self.recent_contacts
+            .retain(|event| event.global_time <= global_time && global_time - event.global_time < CONTACT_STEPS_SHOWN);
+    }
+
+    pub fn put_on_canvas(&self, state: &State, canvas: &mut RGBCanvas, scale: f64) {
+        if self.broadphase_grid {
+            put_grid_on_canvas(state, canvas, scale);
+        }
+
+        if self.bounding_boxes {
+            put_bounding_boxes_on_canvas(state, canvas, scale);
+        }
+
+        if self.time_of_impact {
+            put_impacts_on_canvas(state, canvas, scale);
+        }
+
+        if self.velocities {
+            let style: LineStyle = LineStyle::new(VELOCITY_COLOR).thickness(scale).arrow(5.0 * scale);
+
+            for circle in state.circles.iter().filter(|circle| !circle.is_fluid) {
+                canvas.draw_line(
+                    circle.x_pos * scale,
+                    circle.y_pos * scale,
+                    (circle.x_pos + circle.x_vel * VELOCITY_SCALE) * scale,
+                    (circle.y_pos + circle.y_vel * VELOCITY_SCALE) * scale,
+                    &style,
+                );
+            }
+        }
+
+        if self.contacts {
+            let style: LineStyle = LineStyle::new(CONTACT_COLOR).thickness(scale).arrow(4.0 * scale);
+
+            for event in self.recent_contacts.iter() {
+                canvas.fill_circle(event.contact_x * scale, event.contact_y * scale, 2.5 * scale, CONTACT_COLOR);
+                canvas.draw_line(
+                    event.contact_x * scale,
+                    event.contact_y * scale,
+                    (event.contact_x + event.normal_x * CONTACT_NORMAL_LENGTH) * scale,
+                    (event.contact_y + event.normal_y * CONTACT_NORMAL_LENGTH) * scale,
+                    &style,
+                );
+            }
+        }
+
+        if self.centre_of_mass {
+            put_centres_of_mass_on_canvas(state, canvas, scale);
+        }
+    }
+}
+
+fn put_grid_on_canvas(state: &State, canvas: &mut RGBCanvas, scale: f64) {
+    let cell_size: f64 = state.broadphase_cell_size();
+    let grid: UniformGrid = UniformGrid::new(state.width, state.height, cell_size);
+    let mut is_occupied: Vec<bool> = vec![false; grid.columns * grid.rows];
+
+    for circle in state.circles.iter() {
+        let (column, row) = grid.cell_of(circle.x_pos, circle.y_pos);
+        is_occupied[row * grid.columns + column] = true;
+    }
+
+    let cell_pixels: usize = (cell_size * scale).ceil() as usize;
+
+    for row in 0..grid.rows {
+        for column in 0..grid.columns {
+            if is_occupied[row * grid.columns + column] {
+                canvas.composite_rect(
+                    (column as f64 * cell_size * scale) as i32,
+                    (row as f64 * cell_size * scale) as i32,
+                    cell_pixels,
+                    cell_pixels,
+                    OCCUPIED_CELL_COLOR,
+                    BlendMode::Normal,
+                );
+            }
+        }
+    }
+
+    let style: LineStyle = LineStyle::new(GRID_COLOR).thickness(scale);
+
+    for column in 1..grid.columns {
+        let x: f64 = column as f64 * cell_size * scale;
+        canvas.draw_line(x, 0.0, x, state.height * scale, &style);
+    }
+
+    for row in 1..grid.rows {
+        let y: f64 = row as f64 * cell_size * scale;
+        canvas.draw_line(0.0, y, state.width * scale, y, &style);
+    }
+}
+
+fn put_bounding_boxes_on_canvas(state: &State, canvas: &mut RGBCanvas, scale: f64) {
+    let style: LineStyle = LineStyle::new(BOUNDING_BOX_COLOR).thickness(scale);
+
+    for circle in state.circles.iter() {
+        canvas.draw_rect(
+            (circle.x_pos - circle.radius) * scale,
+            (circle.y_pos - circle.radius) * scale,
+            2.0 * circle.radius * scale,
+            2.0 * circle.radius * scale,
+            &style,
+        );
+    }
+
+    let cluster_style: LineStyle = style.dashed(4.0 * scale, 3.0 * scale);
+
+    for cluster in state.clusters.iter() {
+        let mut bounds: (f64, f64, f64, f64) = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+
+        for circle in state.circles.iter().filter(|circle| circle.cluster_id == Some(cluster.id)) {
+            bounds.0 = bounds.0.min(circle.x_pos - circle.radius);
+            bounds.1 = bounds.1.min(circle.y_pos - circle.radius);
+            bounds.2 = bounds.2.max(circle.x_pos + circle.radius);
+            bounds.3 = bounds.3.max(circle.y_pos + circle.radius);
+        }
+
+        if bounds.0 <= bounds.2 {
+            canvas.draw_rect(
+                (bounds.0 - 2.0) * scale,
+                (bounds.1 - 2.0) * scale,
+                (bounds.2 - bounds.0 + 4.0) * scale,
+                (bounds.3 - bounds.1 + 4.0) * scale,
+                &cluster_style,
+            );
+        }
+    }
+}
+
+// straight-line motion only: walls, forces and other collisions on the way are ignored
+fn put_impacts_on_canvas(state: &State, canvas: &mut RGBCanvas, scale: f64) {
+    let free: Vec<usize> = (0..state.circles.len())
+        .filter(|&index| state.circles[index].cluster_id.is_none() && !state.circles[index].is_fluid)
+        .collect();
+
+    let ghost_style: LineStyle = LineStyle::new(TIME_OF_IMPACT_COLOR).thickness(scale).dashed(3.0 * scale, 3.0 * scale);
+
+    for (n, &i) in free.iter().enumerate() {
+        for &j in free[n + 1..].iter() {
+            let first = &state.circles[i];
+            let second = &state.circles[j];
+
+            let rel_x: f64 = second.x_pos - first.x_pos;
+            let rel_y: f64 = second.y_pos - first.y_pos;
+            let rel_v_x: f64 = second.x_vel - first.x_vel;
+            let rel_v_y: f64 = second.y_vel - first.y_vel;
+            let sum_radii: f64 = first.radius + second.radius;
+
+            // |rel + t * rel_v| = sum_radii, the earlier root is when they first touch
+            let a: f64 = rel_v_x * rel_v_x + rel_v_y * rel_v_y;
+            let b: f64 = 2.0 * (rel_x * rel_v_x + rel_y * rel_v_y);
+            let c: f64 = rel_x * rel_x + rel_y * rel_y - sum_radii * sum_radii;
+            let discriminant: f64 = b * b - 4.0 * a * c;
+
+            if a == 0.0 || c <= 0.0 || b >= 0.0 || discriminant < 0.0 {
+                continue; // not moving relative to each other, already touching or not approaching
+            }
+
+            let t: f64 = (-b - discriminant.sqrt()) / (2.0 * a);
+
+            if t > TIME_OF_IMPACT_HORIZON {
+                continue;
+            }
+
+            for circle in [first, second] {
+                let x: f64 = circle.x_pos + circle.x_vel * t;
+                let y: f64 = circle.y_pos + circle.y_vel * t;
+
+                canvas.draw_circle(x * scale, y * scale, circle.radius * scale, TIME_OF_IMPACT_COLOR, scale);
+                canvas.draw_line(circle.x_pos * scale, circle.y_pos * scale, x * scale, y * scale, &ghost_style);
+            }
+        }
+    }
+}
+
+fn put_centres_of_mass_on_canvas(state: &State, canvas: &mut RGBCanvas, scale: f64) {
+    let style: LineStyle = LineStyle::new(CENTRE_OF_MASS_COLOR).thickness(scale);
+
+    for cluster in state.clusters.iter() {
+        canvas.fill_circle(cluster.x_pos * scale, cluster.y_pos * scale, 2.5 * scale, CENTRE_OF_MASS_COLOR);
+    }
+
+    let total_mass: f64 = state.circles.iter().map(|circle| circle.mass).sum();
+
+    if total_mass > 0.0 {
+        let x: f64 = state.circles.iter().map(|circle| circle.x_pos * circle.mass).sum::<f64>() / total_mass * scale;
+        let y: f64 = state.circles.iter().map(|circle| circle.y_pos * circle.mass).sum::<f64>() / total_mass * scale;
+        let size: f64 = 8.0 * scale;
+
+        canvas.draw_line(x - size, y, x + size, y, &style);
+        canvas.draw_line(x, y - size, x, y + size, &style);
+        canvas.draw_circle(x, y, size / 2.0, CENTRE_OF_MASS_COLOR, scale);
+    }
+}
@@ -0,0 +1,733 @@
+// A small raster library on RGBCanvas: pixel access, clearing, blitting, lines and filled or outlined shapes.
+// Lines are anti-aliased (Xiaolin Wu's algorithm for thin lines, pixel coverage for thick ones), clipped to
+// the canvas first, and can be dashed or end in an arrowhead. Ellipses and polygons are anti-aliased too,
+// rectangles are filled on whole pixels. Pixel centres are at whole coordinates, as for the circles.
+// Shapes are drawn through the Canvas trait, with translucent colours composited by a BlendMode, either straight
+// onto the opaque RGBCanvas or onto an RGBACanvas layer. A layer keeps its own transparency until it is composited
+// onto the scene as a whole, as the trails and fluid regions are, or flattened over a single colour.
+
+use crate::common_structs::{BlendMode, RGBACanvas, RGBAColor, RGBCanvas, RGBColor};
+
+const ARROWHEAD_ANGLE: f64 = std::f64::consts::PI / 6.0; // between the shaft and each barb
+const POLYGON_SUBROWS: usize = 4; // scanlines per pixel row, coverage along each is exact
+
+#[derive(Clone, Copy)]
+pub struct LineStyle {
+    pub color: RGBColor,
+    pub thickness: f64,
+    pub dash: Option<(f64, f64)>, // lengths of the drawn and the skipped parts
+    pub arrowhead: Option<f64>, // length of the barbs at the end of the line
+    pub opacity: f64,
+}
+
+impl LineStyle {
+    pub fn new(color: RGBColor) -> LineStyle {
+        return LineStyle {
+            color,
+            thickness: 1.0,
+            dash: None,
+            arrowhead: None,
+            opacity: 1.0,
+        };
+    }
+
+    pub fn thickness(mut self, thickness: f64) -> LineStyle {
+        self.thickness = thickness;
+        return self;
+    }
+
+    pub fn dashed(mut self, drawn: f64, skipped: f64) -> LineStyle {
+        self.dash = Some((drawn, skipped));
+        return self;
+    }
+
+    pub fn arrow(mut self, head_length: f64) -> LineStyle {
+        self.arrowhead = Some(head_length);
+        return self;
+    }
+
+    pub fn opacity(mut self, opacity: f64) -> LineStyle {
+        self.opacity = opacity;
+        return self;
+    }
+}
+
+impl RGBCanvas {
+    pub fn get_pixel(&self, x: i32, y: i32) -> Option<RGBColor> {
+        let index: usize = self.index(x, y)?;
+
+        return Some(RGBColor {
+            r: self.data[index],
+            g: self.data[index + 1],
+            b: self.data[index + 2],
+        });
+    }
+
+    // pixels off the canvas are ignored
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: RGBColor) {
+        if let Some(index) = self.index(x, y) {
+            self.data[index..index + 3].copy_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+
+    pub fn clear(&mut self, color: RGBColor) {
+        if color.r == color.g && color.g == color.b {
+            self.data.fill(color.r);
+        } else {
+            for pixel in self.data.chunks_exact_mut(3) {
+                pixel.copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+    }
+
+    // copies the whole source with its top left corner at (x, y), clipped to this canvas
+    pub fn blit(&mut self, source: &RGBCanvas, x: i32, y: i32) {
+        self.blit_region(source, 0, 0, source.width, source.height, x, y);
+    }
+
+    // copies a width by height part of the source, starting at (source_x, source_y), to (x, y)
+    pub fn blit_region(&mut self, source: &RGBCanvas, source_x: usize, source_y: usize, width: usize, height: usize, x: i32, y: i32) {
+        // first the part that exists in the source, then the part that lands on this canvas
+        let width: i64 = width.min(source.width.saturating_sub(source_x)) as i64;
+        let height: i64 = height.min(source.height.saturating_sub(source_y)) as i64;
+
+        let skip_x: i64 = i64::max(-(x as i64), 0);
+        let skip_y: i64 = i64::max(-(y as i64), 0);
+        let row_length: i64 = i64::min(width, self.width as i64 - x as i64) - skip_x;
+        let rows: i64 = i64::min(height, self.height as i64 - y as i64) - skip_y;
+
+        if row_length <= 0 || rows <= 0 {
+            return;
+        }
+
+        for row in skip_y..(skip_y + rows) {
+            let from: usize = ((source_y + row as usize) * source.width + source_x + skip_x as usize) * 3;
+            let to: usize = ((y as i64 + row) as usize * self.width + (x as i64 + skip_x) as usize) * 3;
+            let length: usize = row_length as usize * 3;
+
+            self.data[to..to + length].copy_from_slice(&source.data[from..from + length]);
+        }
+    }
+
+    // puts a layer of the same size over the whole canvas
+    pub fn composite(&mut self, layer: &RGBACanvas, mode: BlendMode) {
+        for j in 0..self.height.min(layer.height) {
+            for i in 0..self.width.min(layer.width) {
+                let index: usize = (j * layer.width + i) * 4;
+                let color: RGBAColor = RGBAColor {
+                    r: layer.data[index],
+                    g: layer.data[index + 1],
+                    b: layer.data[index + 2],
+                    a: layer.data[index + 3],
+                };
+
+                self.composite_pixel(i as i32, j as i32, color, 1.0, mode);
+            }
+        }
+    }
+
+    // whole pixels from (x, y) on, clipped to the canvas
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: usize, height: usize, color: RGBColor) {
+        let x_start: usize = x.max(0) as usize;
+        let y_start: usize = y.max(0) as usize;
+        let x_end: usize = (x as i64 + width as i64).clamp(0, self.width as i64) as usize;
+        let y_end: usize = (y as i64 + height as i64).clamp(0, self.height as i64) as usize;
+
+        if x_start >= x_end {
+            return;
+        }
+
+        for j in y_start..y_end {
+            let row_start: usize = (j * self.width + x_start) * 3;
+
+            for pixel in self.data[row_start..row_start + (x_end - x_start) * 3].chunks_exact_mut(3) {
+                pixel.copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+
+        return Some((y as usize * self.width + x as usize) * 3);
+    }
+}
+
+impl RGBACanvas {
+    // the layer over a single colour, as shown in the window
+    pub fn to_rgb(&self, background: RGBColor) -> RGBCanvas {
+        let mut canvas: RGBCanvas = RGBCanvas::new(self.width, self.height);
+        canvas.clear(background);
+        canvas.composite(self, BlendMode::Normal);
+
+        return canvas;
+    }
+}
+
+// everything but whole-pixel access is drawn through composite_pixel, so that the shapes go onto the opaque
+// scene and onto layers alike
+pub trait Canvas {
+    // width and height in pixels
+    fn size(&self) -> (usize, usize);
+
+    // mixes the colour in by its alpha times the covered fraction; pixels off the canvas are ignored
+    fn composite_pixel(&mut self, x: i32, y: i32, color: RGBAColor, coverage: f64, mode: BlendMode);
+
+    fn blend_pixel(&mut self, x: i32, y: i32, color: RGBColor, coverage: f64) {
+        self.composite_pixel(x, y, RGBAColor::from_rgb(color, 255), coverage, BlendMode::Normal);
+    }
+
+    fn draw_line(&mut self, x_start: f64, y_start: f64, x_end: f64, y_end: f64, style: &LineStyle) {
+        if ![x_start, y_start, x_end, y_end].iter().all(|coordinate| coordinate.is_finite()) {
+            return;
+        }
+
+        let length: f64 = (x_end - x_start).hypot(y_end - y_start);
+        let color: RGBAColor = RGBAColor::from_rgb(style.color, (style.opacity.clamp(0.0, 1.0) * 255.0).round() as u8);
+
+        if length == 0.0 {
+            draw_segment(self, x_start, y_start, x_end, y_end, color, style.thickness);
+            return;
+        }
+
+        let dir_x: f64 = (x_end - x_start) / length;
+        let dir_y: f64 = (y_end - y_start) / length;
+
+        match style.dash {
+            Some((drawn, skipped)) if drawn > 0.0 && skipped >= 0.0 => {
+                let mut position: f64 = 0.0;
+
+                while position < length {
+                    let dash_end: f64 = f64::min(position + drawn, length);
+
+                    draw_segment(
+                        self,
+                        x_start + dir_x * position,
+                        y_start + dir_y * position,
+                        x_start + dir_x * dash_end,
+                        y_start + dir_y * dash_end,
+                        color,
+                        style.thickness,
+                    );
+
+                    position += drawn + skipped;
+                }
+            }
+            _ => draw_segment(self, x_start, y_start, x_end, y_end, color, style.thickness),
+        }
+
+        if let Some(head_length) = style.arrowhead {
+            let (sin_a, cos_a) = ARROWHEAD_ANGLE.sin_cos();
+
+            // the barbs point back along the shaft, turned either way
+            for side in [-1.0, 1.0] {
+                draw_segment(
+                    self,
+                    x_end,
+                    y_end,
+                    x_end - head_length * (dir_x * cos_a - side * dir_y * sin_a),
+                    y_end - head_length * (side * dir_x * sin_a + dir_y * cos_a),
+                    color,
+                    style.thickness,
+                );
+            }
+        }
+    }
+
+    // whole pixels as fill_rect, but composited, e.g. for translucent highlights
+    fn composite_rect(&mut self, x: i32, y: i32, width: usize, height: usize, color: RGBAColor, mode: BlendMode) {
+        let (canvas_width, canvas_height) = self.size();
+        let x_end: i32 = (x as i64 + width as i64).clamp(0, canvas_width as i64) as i32;
+        let y_end: i32 = (y as i64 + height as i64).clamp(0, canvas_height as i64) as i32;
+
+        for j in y.max(0)..y_end {
+            for i in x.max(0)..x_end {
+                self.composite_pixel(i, j, color, 1.0, mode);
+            }
+        }
+    }
+
+    fn draw_rect(&mut self, x: f64, y: f64, width: f64, height: f64, style: &LineStyle) {
+        self.draw_polygon(&[(x, y), (x + width, y), (x + width, y + height), (x, y + height)], style);
+    }
+
+    fn fill_circle(&mut self, x_pos: f64, y_pos: f64, radius: f64, color: RGBColor) {
+        self.fill_ellipse(x_pos, y_pos, radius, radius, color);
+    }
+
+    fn draw_circle(&mut self, x_pos: f64, y_pos: f64, radius: f64, color: RGBColor, thickness: f64) {
+        self.draw_ellipse(x_pos, y_pos, radius, radius, color, thickness);
+    }
+
+    // axis-aligned, with radius_x and radius_y the half widths along x and y
+    fn fill_ellipse(&mut self, x_pos: f64, y_pos: f64, radius_x: f64, radius_y: f64, color: RGBColor) {
+        plot_ellipse(self, x_pos, y_pos, radius_x, radius_y, 1.0, |distance| 0.5 - distance, RGBAColor::from_rgb(color, 255), BlendMode::Normal);
+    }
+
+    // as fill_circle, but composited, e.g. for translucent markers
+    fn composite_circle(&mut self, x_pos: f64, y_pos: f64, radius: f64, color: RGBAColor, mode: BlendMode) {
+        plot_ellipse(self, x_pos, y_pos, radius, radius, 1.0, |distance| 0.5 - distance, color, mode);
+    }
+
+    fn draw_ellipse(&mut self, x_pos: f64, y_pos: f64, radius_x: f64, radius_y: f64, color: RGBColor, thickness: f64) {
+        let half: f64 = thickness.max(1.0) / 2.0;
+        let intensity: f64 = thickness.min(1.0); // thin outlines are drawn fainter, as thin lines are
+
+        plot_ellipse(self, x_pos, y_pos, radius_x, radius_y, half + 1.0, |distance| (half + 0.5 - distance.abs()).clamp(0.0, 1.0) * intensity, RGBAColor::from_rgb(color, 255), BlendMode::Normal);
+    }
+
+    // even-odd rule, so self-intersecting polygons get holes
+    fn fill_polygon(&mut self, points: &[(f64, f64)], color: RGBColor) {
+        if points.len() < 3 || !points.iter().all(|(x, y)| x.is_finite() && y.is_finite()) {
+            return;
+        }
+
+        let (width, height) = self.size();
+        let y_min: f64 = points.iter().map(|point| point.1).fold(f64::INFINITY, f64::min);
+        let y_max: f64 = points.iter().map(|point| point.1).fold(f64::NEG_INFINITY, f64::max);
+
+        let row_start: i32 = f64::max(y_min.round(), 0.0) as i32;
+        let row_end: i32 = f64::min(y_max.round(), height as f64 - 1.0) as i32;
+
+        let mut row_coverage: Vec<f64> = vec![0.0; width];
+        let mut crossings: Vec<f64> = Vec::new();
+
+        for j in row_start..(row_end + 1) {
+            row_coverage.fill(0.0);
+
+            for k in 0..POLYGON_SUBROWS {
+                let y: f64 = j as f64 - 0.5 + (k as f64 + 0.5) / POLYGON_SUBROWS as f64;
+
+                crossings.clear();
+                for (index, &(x_a, y_a)) in points.iter().enumerate() {
+                    let (x_b, y_b) = points[(index + 1) % points.len()];
+
+                    // half-open, so that a vertex on the scanline is counted once
+                    if (y_a <= y) != (y_b <= y) {
+                        crossings.push(x_a + (y - y_a) / (y_b - y_a) * (x_b - x_a));
+                    }
+                }
+                crossings.sort_by(|a, b| a.total_cmp(b));
+
+                for span in crossings.chunks_exact(2) {
+                    add_span_coverage(&mut row_coverage, span[0], span[1], 1.0 / POLYGON_SUBROWS as f64);
+                }
+            }
+
+            for (i, coverage) in row_coverage.iter().enumerate() {
+                self.blend_pixel(i as i32, j, color, *coverage);
+            }
+        }
+    }
+
+    // closed outline, the style's arrowhead is left out
+    fn draw_polygon(&mut self, points: &[(f64, f64)], style: &LineStyle) {
+        let edge_style: LineStyle = LineStyle { arrowhead: None, ..*style };
+
+        for (index, &(x_start, y_start)) in points.iter().enumerate() {
+            let (x_end, y_end) = points[(index + 1) % points.len()];
+
+            self.draw_line(x_start, y_start, x_end, y_end, &edge_style);
+        }
+    }
+}
+
+impl Canvas for RGBCanvas {
+    fn size(&self) -> (usize, usize) {
+        return (self.width, self.height);
+    }
+
+    // the canvas is opaque, so the result is the blended colour mixed in by the colour's alpha and the coverage
+    fn composite_pixel(&mut self, x: i32, y: i32, color: RGBAColor, coverage: f64, mode: BlendMode) {
+        let index: usize = match self.index(x, y) {
+            Some(index) if coverage > 0.0 && color.a > 0 => index,
+            _ => return,
+        };
+
+        let alpha: f64 = color.a as f64 / 255.0 * coverage.min(1.0);
+
+        for (offset, channel) in [color.r, color.g, color.b].into_iter().enumerate() {
+            let old: f64 = self.data[index + offset] as f64;
+            self.data[index + offset] = (old + (mode.mix(channel, self.data[index + offset]) - old) * alpha).round() as u8;
+        }
+    }
+}
+
+impl Canvas for RGBACanvas {
+    fn size(&self) -> (usize, usize) {
+        return (self.width, self.height);
+    }
+
+    // source over destination with the blend mode applied where both are present (as in the W3C compositing spec)
+    fn composite_pixel(&mut self, x: i32, y: i32, color: RGBAColor, coverage: f64, mode: BlendMode) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height || coverage <= 0.0 || color.a == 0 {
+            return;
+        }
+
+        let index: usize = (y as usize * self.width + x as usize) * 4;
+        let source_alpha: f64 = color.a as f64 / 255.0 * coverage.min(1.0);
+        let destination_alpha: f64 = self.data[index + 3] as f64 / 255.0;
+        let alpha: f64 = source_alpha + destination_alpha * (1.0 - source_alpha);
+
+        for (offset, channel) in [color.r, color.g, color.b].into_iter().enumerate() {
+            let old: u8 = self.data[index + offset];
+            let source: f64 = (1.0 - destination_alpha) * channel as f64 + destination_alpha * mode.mix(channel, old);
+
+            self.data[index + offset] = ((source_alpha * source + destination_alpha * (1.0 - source_alpha) * old as f64) / alpha).round() as u8;
+        }
+
+        self.data[index + 3] = (alpha * 255.0).round() as u8;
+    }
+}
+
+impl BlendMode {
+    // colour of one channel where the source covers the destination completely
+    fn mix(self, source: u8, destination: u8) -> f64 {
+        return match self {
+            BlendMode::Normal => source as f64,
+            BlendMode::Additive => f64::min(source as f64 + destination as f64, 255.0),
+            BlendMode::Multiply => source as f64 * destination as f64 / 255.0,
+        };
+    }
+}
+
+// blends every pixel near the ellipse by coverage(signed distance from the outline, negative inside)
+fn plot_ellipse<C: Canvas + ?Sized>(canvas: &mut C, x_pos: f64, y_pos: f64, radius_x: f64, radius_y: f64, margin: f64, coverage: impl Fn(f64) -> f64, color: RGBAColor, mode: BlendMode) {
+    if radius_x <= 0.0 || radius_y <= 0.0 || ![x_pos, y_pos, radius_x, radius_y].iter().all(|value| value.is_finite()) {
+        return;
+    }
+
+    let (width, height) = canvas.size();
+    let box_lx: i32 = f64::max(x_pos - radius_x - margin, 0.0) as i32;
+    let box_hx: i32 = f64::min(x_pos + radius_x + margin, width as f64 - 1.0) as i32;
+    let box_ly: i32 = f64::max(y_pos - radius_y - margin, 0.0) as i32;
+    let box_hy: i32 = f64::min(y_pos + radius_y + margin, height as f64 - 1.0) as i32;
+
+    for j in box_ly..(box_hy + 1) {
+        for i in box_lx..(box_hx + 1) {
+            let dx: f64 = i as f64 - x_pos;
+            let dy: f64 = j as f64 - y_pos;
+
+            // the implicit function over its gradient, exact for circles and close for ellipses
+            let value: f64 = (dx / radius_x).powi(2) + (dy / radius_y).powi(2) - 1.0;
+            let gradient: f64 = 2.0 * (dx / (radius_x * radius_x)).hypot(dy / (radius_y * radius_y));
+            let distance: f64 = if gradient > 0.0 {value / gradient} else {-radius_x.min(radius_y)};
+
+            canvas.composite_pixel(i, j, color, coverage(distance), mode);
+        }
+    }
+}
+
+// adds how much of each pixel of the row (from i - 0.5 to i + 0.5) lies between x_start and x_end
+fn add_span_coverage(row_coverage: &mut [f64], x_start: f64, x_end: f64, weight: f64) {
+    let width: usize = row_coverage.len();
+
+    if width == 0 || x_end < -0.5 || x_start > width as f64 - 0.5 {
+        return;
+    }
+
+    let first: usize = f64::max((x_start + 0.5).floor(), 0.0) as usize;
+    let last: usize = f64::min((x_end + 0.5).floor(), width as f64 - 1.0) as usize;
+
+    for (i, coverage) in row_coverage.iter_mut().enumerate().take(last + 1).skip(first) {
+        let overlap: f64 = f64::min(x_end, i as f64 + 0.5) - f64::max(x_start, i as f64 - 0.5);
+
+        *coverage += overlap.max(0.0) * weight;
+    }
+}
+
+fn draw_segment<C: Canvas + ?Sized>(canvas: &mut C, x_start: f64, y_start: f64, x_end: f64, y_end: f64, color: RGBAColor, thickness: f64) {
+    let (width, height) = canvas.size();
+
+    // the margin keeps the anti-aliased fringe of lines running just off the edge
+    let margin: f64 = thickness / 2.0 + 1.0;
+
+    let clipped = clip_segment(
+        (x_start, y_start, x_end, y_end),
+        (-margin, -margin, width as f64 - 1.0 + margin, height as f64 - 1.0 + margin),
+    );
+
+    if let Some((x_start, y_start, x_end, y_end)) = clipped {
+        if thickness <= 1.0 {
+            draw_wu_segment(canvas, x_start, y_start, x_end, y_end, color, thickness);
+        } else {
+            draw_thick_segment(canvas, x_start, y_start, x_end, y_end, color, thickness);
+        }
+    }
+}
+
+// Xiaolin Wu's algorithm, thinner lines are drawn fainter
+fn draw_wu_segment<C: Canvas + ?Sized>(canvas: &mut C, mut x_start: f64, mut y_start: f64, mut x_end: f64, mut y_end: f64, color: RGBAColor, intensity: f64) {
+    let is_steep: bool = (y_end - y_start).abs() > (x_end - x_start).abs();
+
+    // walks along x, so steep lines are drawn with x and y swapped
+    if is_steep {
+        std::mem::swap(&mut x_start, &mut y_start);
+        std::mem::swap(&mut x_end, &mut y_end);
+    }
+
+    if x_start > x_end {
+        std::mem::swap(&mut x_start, &mut x_end);
+        std::mem::swap(&mut y_start, &mut y_end);
+    }
+
+    let gradient: f64 = if x_end - x_start == 0.0 {1.0} else {(y_end - y_start) / (x_end - x_start)};
+
+    let mut plot = |x: f64, y: f64, coverage: f64| {
+        if is_steep {
+            canvas.composite_pixel(y as i32, x as i32, color, coverage * intensity, BlendMode::Normal);
+        } else {
+            canvas.composite_pixel(x as i32, y as i32, color, coverage * intensity, BlendMode::Normal);
+        }
+    };
+
+    // first end point
+    let x_first: f64 = x_start.round();
+    let y_first: f64 = y_start + gradient * (x_first - x_start);
+    let x_gap: f64 = 1.0 - fraction(x_start + 0.5);
+    plot(x_first, y_first.floor(), (1.0 - fraction(y_first)) * x_gap);
+    plot(x_first, y_first.floor() + 1.0, fraction(y_first) * x_gap);
+
+    // second end point
+    let x_last: f64 = x_end.round();
+    let y_last: f64 = y_end + gradient * (x_last - x_end);
+    let x_gap: f64 = fraction(x_end + 0.5);
+    plot(x_last, y_last.floor(), (1.0 - fraction(y_last)) * x_gap);
+    plot(x_last, y_last.floor() + 1.0, fraction(y_last) * x_gap);
+
+    let mut y: f64 = y_first + gradient;
+    let mut x: f64 = x_first + 1.0;
+
+    while x < x_last {
+        plot(x, y.floor(), 1.0 - fraction(y));
+        plot(x, y.floor() + 1.0, fraction(y));
+
+        y += gradient;
+        x += 1.0;
+    }
+}
+
+// every pixel within half the thickness of the segment, with round ends
+fn draw_thick_segment<C: Canvas + ?Sized>(canvas: &mut C, x_start: f64, y_start: f64, x_end: f64, y_end: f64, color: RGBAColor, thickness: f64) {
+    let (width, height) = canvas.size();
+    let half: f64 = thickness / 2.0;
+
+    let box_lx: i32 = f64::max(x_start.min(x_end) - half - 1.0, 0.0) as i32;
+    let box_hx: i32 = f64::min(x_start.max(x_end) + half + 1.0, width as f64 - 1.0) as i32;
+    let box_ly: i32 = f64::max(y_start.min(y_end) - half - 1.0, 0.0) as i32;
+    let box_hy: i32 = f64::min(y_start.max(y_end) + half + 1.0, height as f64 - 1.0) as i32;
+
+    let delta_x: f64 = x_end - x_start;
+    let delta_y: f64 = y_end - y_start;
+    let length_squared: f64 = delta_x * delta_x + delta_y * delta_y;
+
+    for j in box_ly..(box_hy + 1) {
+        for i in box_lx..(box_hx + 1) {
+            // nearest point of the segment
+            let t: f64 = if length_squared > 0.0 {
+                (((i as f64 - x_start) * delta_x + (j as f64 - y_start) * delta_y) / length_squared).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+
+            let distance: f64 = (i as f64 - x_start - t * delta_x).hypot(j as f64 - y_start - t * delta_y);
+
+            canvas.composite_pixel(i, j, color, half + 0.5 - distance, BlendMode::Normal);
+        }
+    }
+}
+
+// fractional part that stays positive left of zero, as the margin lets lines start there
+fn fraction(value: f64) -> f64 {
+    return value - value.floor();
+}
+
+// Liang-Barsky clipping to (x_min, y_min, x_max, y_max), None when nothing is left
+fn clip_segment(segment: (f64, f64, f64, f64), bounds: (f64, f64, f64, f64)) -> Option<(f64, f64, f64, f64)> {
+    let (x_start, y_start, x_end, y_end) = segment;
+    let (x_min, y_min, x_max, y_max) = bounds;
+
+    let delta_x: f64 = x_end - x_start;
+    let delta_y: f64 = y_end - y_start;
+
+    let mut t_enter: f64 = 0.0;
+    let mut t_exit: f64 = 1.0;
+
+    for (p, q) in [
+        (-delta_x, x_start - x_min),
+        (delta_x, x_max - x_start),
+        (-delta_y, y_start - y_min),
+        (delta_y, y_max - y_start),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None; // parallel to this edge and outside it
+            }
+        } else {
+            let t: f64 = q / p;
+
+            if p < 0.0 {
+                t_enter = t_enter.max(t);
+            } else {
+                t_exit = t_exit.min(t);
+            }
+        }
+    }
+
+    if t_enter > t_exit {
+        return None;
+    }
+
+    return Some((
+        x_start + t_enter * delta_x,
+        y_start + t_enter * delta_y,
+        x_start + t_exit * delta_x,
+        y_start + t_exit * delta_y,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: RGBColor = RGBColor { r: 255, g: 255, b: 255 };
+    const RED: RGBColor = RGBColor { r: 255, g: 0, b: 0 };
+
+    // every pixel tells where it came from: red is the column, green the row
+    fn numbered_canvas(width: usize, height: usize) -> RGBCanvas {
+        let mut canvas: RGBCanvas = RGBCanvas::new(width, height);
+
+        for j in 0..height {
+            for i in 0..width {
+                canvas.set_pixel(i as i32, j as i32, RGBColor { r: i as u8, g: j as u8, b: 1 });
+            }
+        }
+
+        return canvas;
+    }
+
+    #[test]
+    fn pixels_off_the_canvas_are_ignored() {
+        let mut canvas: RGBCanvas = RGBCanvas::new(4, 3);
+
+        canvas.set_pixel(2, 1, RED);
+        for (x, y) in [(-1, 0), (0, -1), (4, 0), (0, 3), (i32::MAX, i32::MIN)] {
+            canvas.set_pixel(x, y, WHITE);
+            assert!(canvas.get_pixel(x, y).is_none());
+        }
+
+        assert!(canvas.get_pixel(2, 1) == Some(RED));
+        assert_eq!(canvas.data.iter().filter(|value| **value != 0).count(), 1);
+    }
+
+    #[test]
+    fn clear_fills_every_pixel() {
+        let mut canvas: RGBCanvas = RGBCanvas::new(3, 2);
+
+        canvas.clear(RED);
+        assert!(canvas.data.chunks_exact(3).all(|pixel| pixel == [255, 0, 0]));
+
+        canvas.clear(WHITE);
+        assert!(canvas.data.iter().all(|value| *value == 255));
+    }
+
+    #[test]
+    fn blit_is_clipped_on_every_side() {
+        let source: RGBCanvas = numbered_canvas(4, 4);
+
+        for (x, y) in [(-2, -1), (3, 2), (-10, 0), (0, 10), (-1, -1)] {
+            let mut canvas: RGBCanvas = RGBCanvas::new(5, 5);
+            canvas.blit(&source, x, y);
+
+            for j in 0..5 {
+                for i in 0..5 {
+                    let (source_x, source_y) = (i - x, j - y);
+                    let expected: RGBColor = if (0..4).contains(&source_x) && (0..4).contains(&source_y) {
+                        RGBColor { r: source_x as u8, g: source_y as u8, b: 1 }
+                    } else {
+                        RGBColor { r: 0, g: 0, b: 0 }
+                    };
+
+                    assert!(canvas.get_pixel(i, j) == Some(expected), "pixel {} {} after a blit to {} {}", i, j, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blit_region_starts_at_the_source_offset() {
+        let source: RGBCanvas = numbered_canvas(6, 5);
+        let mut canvas: RGBCanvas = RGBCanvas::new(4, 4);
+
+        // asks for more than is left of the source, only 2 by 2 exist from (4, 3)
+        canvas.blit_region(&source, 4, 3, 3, 3, 1, 1);
+
+        for j in 0..4 {
+            for i in 0..4 {
+                let expected: RGBColor = if (1..3).contains(&i) && (1..3).contains(&j) {
+                    RGBColor { r: (i + 3) as u8, g: (j + 2) as u8, b: 1 }
+                } else {
+                    RGBColor { r: 0, g: 0, b: 0 }
+                };
+
+                assert!(canvas.get_pixel(i, j) == Some(expected), "pixel {} {}", i, j);
+            }
+        }
+
+        // a region starting outside the source copies nothing
+        let before: Vec<u8> = canvas.data.clone();
+        canvas.blit_region(&source, 6, 0, 2, 2, 0, 0);
+        assert_eq!(canvas.data, before);
+    }
+
+    #[test]
+    fn layer_matches_drawing_straight_onto_the_canvas() {
+        let grey: RGBColor = RGBColor { r: 90, g: 120, b: 150 };
+        let blue: RGBAColor = RGBAColor { r: 0, g: 0, b: 255, a: 160 };
+        let mut straight: RGBCanvas = RGBCanvas::new(10, 10);
+        let mut layer: RGBACanvas = RGBACanvas::new(10, 10);
+
+        straight.clear(grey);
+        for canvas in [&mut straight as &mut dyn Canvas, &mut layer] {
+            canvas.composite_rect(1, 1, 6, 6, RGBAColor::from_rgb(RED, 128), BlendMode::Normal);
+            canvas.composite_circle(6.0, 6.0, 3.0, blue, BlendMode::Normal);
+            canvas.draw_line(0.0, 9.0, 9.0, 0.0, &LineStyle::new(WHITE).thickness(2.0).opacity(0.5));
+        }
+
+        let flattened: RGBCanvas = layer.to_rgb(grey);
+        for (a, b) in straight.data.iter().zip(flattened.data.iter()) {
+            assert!((*a as i32 - *b as i32).abs() <= 1, "{} and {}", a, b);
+        }
+    }
+
+    #[test]
+    fn empty_layer_leaves_the_canvas_alone() {
+        let mut canvas: RGBCanvas = numbered_canvas(5, 4);
+        let before: Vec<u8> = canvas.data.clone();
+
+        canvas.composite(&RGBACanvas::new(5, 4), BlendMode::Multiply);
+        assert_eq!(canvas.data, before);
+    }
+
+    #[test]
+    fn concave_polygon_leaves_its_notch_empty() {
+        let mut canvas: RGBCanvas = RGBCanvas::new(12, 12);
+
+        // a U standing on its base, open between x = 3 and x = 6 above y = 3
+        let points: [(f64, f64); 8] = [(0.0, 0.0), (9.0, 0.0), (9.0, 9.0), (6.0, 9.0), (6.0, 3.0), (3.0, 3.0), (3.0, 9.0), (0.0, 9.0)];
+        canvas.fill_polygon(&points, WHITE);
+
+        let red_at = |x: i32, y: i32| -> u8 { canvas.get_pixel(x, y).unwrap().r };
+
+        assert_eq!(red_at(1, 5), 255); // left arm
+        assert_eq!(red_at(7, 5), 255); // right arm
+        assert_eq!(red_at(4, 1), 255); // base
+        assert_eq!(red_at(4, 6), 0); // notch
+        assert_eq!(red_at(4, 8), 0);
+        assert_eq!(red_at(10, 5), 0); // outside
+        assert!((120..=135).contains(&red_at(0, 5))); // half covered by the left edge
+        assert!((120..=135).contains(&red_at(3, 6))); // half covered by the inner edge
+    }
+}
@@ -0,0 +1,61 @@
+// Errors of the simulation, its drawing and its files, returned where callers can recover and tell the user.
+// Errors of scenario, recording, script and other files leave the path out, the caller knows which file it was.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SimulationError {
+    #[error("The new background has {actual} bytes, a world of this size needs {expected}")]
+    BackgroundSize { expected: usize, actual: usize },
+
+    #[error("Could not read the background image {path}: {message}")]
+    BackgroundImage { path: String, message: String },
+
+    #[error("A circle at ({x:.1}, {y:.1}) would overlap another circle")]
+    CircleOverlap { x: f64, y: f64 },
+
+    #[error("There is no circle with id {0}")]
+    UnknownCircle(usize),
+
+    #[error("A circle cannot be called \"{name}\": {reason}")]
+    InvalidName { name: String, reason: &'static str },
+
+    #[error("The heatmap cell size should be a number of at least 1, not {0}")]
+    HeatmapCellSize(f64),
+
+    #[error("Could not show the image: {0}")]
+    Image(#[from] fltk::prelude::FltkError),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Ron(#[from] ron::error::SpannedError),
+
+    #[error("Could not serialize: {0}")]
+    RonWrite(#[from] ron::Error),
+
+    #[error("{0}")]
+    Scenario(String),
+
+    #[error("{0}")]
+    Recording(String),
+
+    #[error("{0}")]
+    Script(String),
+
+    #[error("{0}")]
+    FlowField(String),
+
+    #[error("{0}")]
+    Video(String),
+
+    #[error("The file name should end in {0}")]
+    FileFormat(&'static str),
+
+    #[error("{0}")]
+    Png(#[from] png::EncodingError),
+
+    #[error("{0}")]
+    Gif(#[from] gif::EncodingError),
+}
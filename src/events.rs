@@ -0,0 +1,52 @@
+// Collision events produced by State every step
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionEvent {
+    pub global_time: usize, // step in which the collision happened
+    pub circle_a: usize, // circle ids, circle_a always has the smaller id
+    pub circle_b: usize,
+    pub contact_x: f64,
+    pub contact_y: f64,
+    pub normal_x: f64, // unit normal pointing from circle_a to circle_b
+    pub normal_y: f64,
+    pub relative_speed: f64, // approach speed along the normal before the collision
+    pub impulse: f64, // magnitude of the momentum exchanged
+    pub time_in_step: f64, // fraction of the step at which the circles touched, 1.0 when not resolved exactly
+    pub is_sticky: bool, // the circles stuck together instead of bouncing
+}
+
+// anything that wants to hear about collisions as they happen
+pub trait CollisionObserver {
+    fn on_collision(&mut self, event: &CollisionEvent);
+}
+
+impl CollisionEvent {
+    // keeps circle_a as the smaller id, flipping the normal if the circles have to be swapped
+    pub fn ordered(mut self) -> CollisionEvent {
+        if self.circle_a > self.circle_b {
+            std::mem::swap(&mut self.circle_a, &mut self.circle_b);
+            self.normal_x = -self.normal_x;
+            self.normal_y = -self.normal_y;
+        }
+
+        return self;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SensorEventKind {
+    Enter, // circle centre moved into a rectangle or circle sensor
+    Exit,
+    Crossing {
+        forward: bool, // moved to the side the line normal points to, see Sensor
+    },
+}
+
+// a circle passing a sensor, produced by State every step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorEvent {
+    pub global_time: usize,
+    pub sensor_id: usize,
+    pub circle_id: usize,
+    pub kind: SensorEventKind,
+}
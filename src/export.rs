@@ -0,0 +1,73 @@
+// Saving a rendered view as an image file, PNG or binary PPM depending on the extension
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{common_structs::RGBCanvas, error::SimulationError};
+
+pub const MAX_EXPORT_SCALE: usize = 8;
+
+pub fn save_image(canvas: &RGBCanvas, path: &str) -> Result<(), SimulationError> {
+    let lowercase_path: String = path.to_lowercase();
+
+    if !lowercase_path.ends_with(".png") && !lowercase_path.ends_with(".ppm") {
+        return Err(SimulationError::FileFormat(".png or .ppm"));
+    }
+
+    let file: File = File::create(path)?;
+    let mut writer: BufWriter<File> = BufWriter::new(file);
+
+    if lowercase_path.ends_with(".png") {
+        let mut encoder = png::Encoder::new(writer, canvas.width as u32, canvas.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder
+            .write_header()
+            .and_then(|mut png_writer| png_writer.write_image_data(&canvas.data))?;
+
+        return Ok(());
+    }
+
+    writeln!(writer, "P6\n{} {}\n255", canvas.width, canvas.height)
+        .and_then(|_| writer.write_all(&canvas.data))
+        .and_then(|_| writer.flush())?;
+
+    return Ok(());
+}
+
+// e.g. floating_objects_2026-10-19_14-03-22.png, in UTC
+pub fn timestamped_file_name(extension: &str) -> String {
+    let seconds: u64 = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (year, month, day) = civil_date((seconds / 86400) as i64);
+    let time_of_day: u64 = seconds % 86400;
+
+    return format!(
+        "floating_objects_{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        extension,
+    );
+}
+
+// days since 1970-01-01 to year, month and day of the Gregorian calendar
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let shifted: i64 = days + 719468; // count from 0000-03-01, so that leap days come last in the year
+    let era: i64 = shifted.div_euclid(146097);
+    let day_of_era: i64 = shifted - era * 146097;
+    let year_of_era: i64 = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index: i64 = (5 * day_of_year + 2) / 153; // 0 is March
+    let day: u32 = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month: u32 = if month_index < 10 {month_index + 3} else {month_index - 9} as u32;
+    let year: i64 = year_of_era + era * 400 + if month <= 2 {1} else {0};
+
+    return (year, month, day);
+}
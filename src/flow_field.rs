@@ -0,0 +1,188 @@
+// Background flow fields (wind, vortex, shear or a grid from a file) that drag circles along
+
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common_structs::{RGBCanvas, RGBColor},
+    drawing::{Canvas, LineStyle},
+    error::SimulationError,
+};
+
+const ARROW_SPACING: i32 = 32; // distance between arrows when the field is drawn
+const ARROW_SCALE: f64 = 8.0; // arrow length in pixels per unit of flow velocity
+const ARROW_COLOR: RGBColor = RGBColor {
+    r: 90,
+    g: 160,
+    b: 90,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum FlowField {
+    Uniform {
+        x_vel: f64,
+        y_vel: f64,
+    },
+    Vortex {
+        x_pos: f64,
+        y_pos: f64,
+        strength: f64, // tangential velocity at the edge of the core
+        core_radius: f64,
+    },
+    Shear {
+        rate: f64, // horizontal velocity gained per pixel of height below the reference line
+        reference_y: f64,
+    },
+    Grid {
+        columns: usize,
+        rows: usize,
+        cell_width: f64,
+        cell_height: f64,
+        velocities: Vec<(f64, f64)>, // row by row, sampled at cell centres
+    },
+}
+
+impl FlowField {
+    pub fn velocity_at(&self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            FlowField::Uniform { x_vel, y_vel } => return (*x_vel, *y_vel),
+            FlowField::Vortex { x_pos, y_pos, strength, core_radius } => {
+                let rel_x: f64 = x - x_pos;
+                let rel_y: f64 = y - y_pos;
+                let distance: f64 = f64::sqrt(rel_x * rel_x + rel_y * rel_y);
+
+                if distance == 0.0 {
+                    return (0.0, 0.0);
+                }
+
+                // solid body rotation inside the core, decaying as 1/r outside (Rankine vortex)
+                let speed: f64 = if distance < *core_radius {
+                    strength * distance / core_radius
+                } else {
+                    strength * core_radius / distance
+                };
+
+                return (-speed * rel_y / distance, speed * rel_x / distance);
+            }
+            FlowField::Shear { rate, reference_y } => return (rate * (y - reference_y), 0.0),
+            FlowField::Grid { columns, rows, cell_width, cell_height, velocities } => {
+                // bilinear interpolation between cell centres
+                let g_x: f64 = f64::clamp(x / cell_width - 0.5, 0.0, (*columns - 1) as f64);
+                let g_y: f64 = f64::clamp(y / cell_height - 0.5, 0.0, (*rows - 1) as f64);
+
+                let column: usize = usize::min(g_x as usize, columns - 1);
+                let row: usize = usize::min(g_y as usize, rows - 1);
+                let next_column: usize = usize::min(column + 1, columns - 1);
+                let next_row: usize = usize::min(row + 1, rows - 1);
+
+                let f_x: f64 = g_x - column as f64;
+                let f_y: f64 = g_y - row as f64;
+
+                let top_left = velocities[row * columns + column];
+                let top_right = velocities[row * columns + next_column];
+                let bottom_left = velocities[next_row * columns + column];
+                let bottom_right = velocities[next_row * columns + next_column];
+
+                let top_x: f64 = top_left.0 * (1.0 - f_x) + top_right.0 * f_x;
+                let top_y: f64 = top_left.1 * (1.0 - f_x) + top_right.1 * f_x;
+                let bottom_x: f64 = bottom_left.0 * (1.0 - f_x) + bottom_right.0 * f_x;
+                let bottom_y: f64 = bottom_left.1 * (1.0 - f_x) + bottom_right.1 * f_x;
+
+                return (top_x * (1.0 - f_y) + bottom_x * f_y, top_y * (1.0 - f_y) + bottom_y * f_y);
+            }
+        }
+    }
+
+    // file format: first line is "columns rows", then one line per row of "x_vel,y_vel" pairs separated by spaces
+    pub fn load_grid(path: &str, world_width: f64, world_height: f64) -> Result<FlowField, SimulationError> {
+        let contents: String = fs::read_to_string(path)?;
+
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+
+        let header: Vec<usize> = match lines.next() {
+            Some(line) => line.split_whitespace().filter_map(|value| value.parse().ok()).collect(),
+            None => return Err(SimulationError::FlowField(String::from("Flow field file is empty"))),
+        };
+
+        if header.len() != 2 || header[0] == 0 || header[1] == 0 {
+            return Err(SimulationError::FlowField(String::from("Flow field file should start with \"columns rows\"")));
+        }
+
+        let columns: usize = header[0];
+        let rows: usize = header[1];
+        let mut velocities: Vec<(f64, f64)> = Vec::with_capacity(columns * rows);
+
+        for (row, line) in lines.take(rows).enumerate() {
+            for pair in line.split_whitespace() {
+                let components: Vec<f64> = pair.split(',').filter_map(|value| value.trim().parse().ok()).collect();
+
+                if components.len() != 2 {
+                    return Err(SimulationError::FlowField(format!("Bad velocity \"{}\" in row {} of the flow field", pair, row + 1)));
+                }
+
+                velocities.push((components[0], components[1]));
+            }
+
+            if velocities.len() != (row + 1) * columns {
+                return Err(SimulationError::FlowField(format!("Row {} of the flow field should have {} velocities", row + 1, columns)));
+            }
+        }
+
+        if velocities.len() != columns * rows {
+            return Err(SimulationError::FlowField(format!("Flow field should have {} rows", rows)));
+        }
+
+        return Ok(FlowField::Grid {
+            columns,
+            rows,
+            cell_width: world_width / columns as f64,
+            cell_height: world_height / rows as f64,
+            velocities,
+        });
+    }
+
+    // draws a regular lattice of arrows showing the local flow velocity
+    // scale is the number of canvas pixels per unit of the world
+    pub fn put_on_canvas(&self, canvas: &mut RGBCanvas, scale: f64) {
+        let width: i32 = canvas.width as i32;
+        let height: i32 = canvas.height as i32;
+        let spacing: i32 = (ARROW_SPACING as f64 * scale) as i32;
+
+        let mut y: i32 = spacing / 2;
+
+        while y < height {
+            let mut x: i32 = spacing / 2;
+
+            while x < width {
+                let (x_vel, y_vel) = self.velocity_at(x as f64 / scale, y as f64 / scale);
+
+                put_arrow_on_canvas(
+                    canvas,
+                    x as f64,
+                    y as f64,
+                    x as f64 + x_vel * ARROW_SCALE * scale,
+                    y as f64 + y_vel * ARROW_SCALE * scale,
+                    scale,
+                );
+
+                x += spacing;
+            }
+
+            y += spacing;
+        }
+    }
+}
+
+fn put_arrow_on_canvas(canvas: &mut RGBCanvas, x_start: f64, y_start: f64, x_end: f64, y_end: f64, scale: f64) {
+    let length: f64 = f64::sqrt((x_end - x_start) * (x_end - x_start) + (y_end - y_start) * (y_end - y_start));
+
+    if length < scale {
+        canvas.blend_pixel(x_start.round() as i32, y_start.round() as i32, ARROW_COLOR, 1.0);
+        return;
+    }
+
+    let head_length: f64 = f64::min(length / 3.0, 5.0 * scale);
+
+    canvas.draw_line(x_start, y_start, x_end, y_end, &LineStyle::new(ARROW_COLOR).thickness(scale).arrow(head_length));
+}
@@ -0,0 +1,129 @@
+// Fluid regions: rectangles filled with liquid that push circles up and slow them down
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common_structs::{BlendMode, RGBACanvas, RGBAColor, RGBColor},
+    drawing::Canvas,
+};
+
+const INTEGRATION_SLICES: usize = 32;
+const FILL_OPACITY: f64 = 0.3;
+const SURFACE_OPACITY: f64 = 0.6;
+const SURFACE_THICKNESS: f64 = 3.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FluidRegion {
+    pub x_start: f64,
+    pub x_end: f64,
+    pub y_start: f64, // top of the region, this is where the surface is drawn
+    pub y_end: f64,
+    pub density: f64,
+    pub viscosity: f64,
+    pub color: RGBColor,
+}
+
+impl FluidRegion {
+    pub fn new(x_start: f64, x_end: f64, y_start: f64, y_end: f64, density: f64, viscosity: f64, color: RGBColor) -> FluidRegion {
+        return FluidRegion {
+            x_start,
+            x_end,
+            y_start,
+            y_end,
+            density,
+            viscosity,
+            color,
+        };
+    }
+
+    // whole width of the world, from the water line down to the bottom
+    pub fn below_water_line(width: f64, height: f64, water_line: f64, density: f64, viscosity: f64) -> FluidRegion {
+        return FluidRegion::new(
+            0.0,
+            width,
+            water_line,
+            height,
+            density,
+            viscosity,
+            RGBColor {
+                r: 40,
+                g: 90,
+                b: 200,
+            },
+        );
+    }
+
+    // returns submerged area of a circle and the centroid of that area (centre of buoyancy)
+    pub fn submerged_area(&self, x_pos: f64, y_pos: f64, radius: f64) -> (f64, f64, f64) {
+        let x_low: f64 = f64::max(x_pos - radius, self.x_start);
+        let x_high: f64 = f64::min(x_pos + radius, self.x_end);
+
+        if x_low >= x_high || y_pos - radius >= self.y_end || y_pos + radius <= self.y_start {
+            return (0.0, x_pos, y_pos);
+        }
+
+        // integrate vertical chords of the circle clipped to the region
+        let slice_width: f64 = (x_high - x_low) / INTEGRATION_SLICES as f64;
+
+        let mut area: f64 = 0.0;
+        let mut moment_x: f64 = 0.0;
+        let mut moment_y: f64 = 0.0;
+
+        let mut x: f64;
+        let mut half_chord: f64;
+        let mut chord_low: f64;
+        let mut chord_high: f64;
+        let mut slice_area: f64;
+
+        for i in 0..INTEGRATION_SLICES {
+            x = x_low + (i as f64 + 0.5) * slice_width;
+            half_chord = f64::sqrt(f64::max(radius * radius - (x - x_pos) * (x - x_pos), 0.0));
+
+            chord_low = f64::max(y_pos - half_chord, self.y_start);
+            chord_high = f64::min(y_pos + half_chord, self.y_end);
+
+            if chord_high > chord_low {
+                slice_area = (chord_high - chord_low) * slice_width;
+
+                area += slice_area;
+                moment_x += slice_area * x;
+                moment_y += slice_area * (chord_low + chord_high) / 2.0;
+            }
+        }
+
+        if area == 0.0 {
+            return (0.0, x_pos, y_pos);
+        }
+
+        return (area, moment_x / area, moment_y / area);
+    }
+
+    pub fn scaled(&self, factor: f64) -> FluidRegion {
+        let mut region: FluidRegion = self.clone();
+
+        region.x_start *= factor;
+        region.x_end *= factor;
+        region.y_start *= factor;
+        region.y_end *= factor;
+
+        return region;
+    }
+
+    // fluid is drawn on top of the circles, so that submerged parts look like they are under water
+    pub fn put_on_canvas(&self, canvas: &mut RGBACanvas) {
+        let box_lx: i32 = f64::max(self.x_start, 0.0) as i32;
+        let box_hx: i32 = f64::min(self.x_end, canvas.width as f64) as i32;
+        let box_ly: i32 = f64::max(self.y_start, 0.0) as i32;
+        let box_hy: i32 = f64::min(self.y_end, canvas.height as f64) as i32;
+
+        let mut opacity: f64;
+
+        for j in box_ly..box_hy {
+            opacity = if (j as f64) < self.y_start + SURFACE_THICKNESS {SURFACE_OPACITY} else {FILL_OPACITY};
+
+            for i in box_lx..box_hx {
+                canvas.composite_pixel(i, j, RGBAColor::from_rgb(self.color, 255), opacity, BlendMode::Normal);
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Runs the simulation without opening a window:
+//     floating_objects --headless [--steps N] [--seed S] [--scenario world.ron] [--script scenario.rhai]
+//     floating_objects --headless --replay recording.ron
+// either can also write a video of the run:
+//     --video movie.gif|movie.y4m|frames.png [--video-every N] [--video-size WxH] [--fps F]
+//         [--caption "text"] [--timestamp]
+
+use crate::{
+    events::CollisionEvent,
+    scenario::Scenario,
+    scripting::Script,
+    session::{Message, Question, Session, UserInterface},
+    state::State,
+    video::{self, VideoOptions, VideoRecorder},
+};
+
+const DEFAULT_STEPS: usize = 1000;
+
+pub struct HeadlessOptions {
+    pub steps: usize,
+    pub scenario_path: Option<String>,
+    pub script_path: Option<String>,
+    pub replay_path: Option<String>,
+    pub video: Option<VideoOptions>,
+}
+
+// recorded answers stand in for the dialogs, so nothing is ever asked
+struct HeadlessInterface;
+
+impl UserInterface for HeadlessInterface {
+    fn answer(&mut self, _question: &Question) -> Option<String> {
+        return None;
+    }
+
+    fn alert(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    fn inform(&mut self, _message: &str) {
+        // already printed by the session
+    }
+
+    fn set_title(&mut self, _title: &str) {}
+
+    fn quit(&mut self) {}
+}
+
+// value following a flag, e.g. "--steps 500"
+pub fn argument_value<'a>(arguments: &'a Vec<String>, flag: &str) -> Option<&'a String> {
+    let position: usize = arguments.iter().position(|argument| argument == flag)?;
+
+    return arguments.get(position + 1);
+}
+
+impl HeadlessOptions {
+    // None when the program should start with its window as usual
+    pub fn from_arguments(arguments: &Vec<String>) -> Option<Result<HeadlessOptions, String>> {
+        if !arguments.iter().any(|argument| argument == "--headless") {
+            return None;
+        }
+
+        let steps: usize = match number_argument(arguments, "--steps", DEFAULT_STEPS) {
+            Ok(steps) => steps,
+            Err(message) => return Some(Err(message)),
+        };
+
+        let video: Option<VideoOptions> = match argument_value(arguments, "--video") {
+            Some(path) => match video_options(arguments, path) {
+                Ok(options) => Some(options),
+                Err(message) => return Some(Err(message)),
+            },
+            None => None,
+        };
+
+        return Some(Ok(HeadlessOptions {
+            steps,
+            video,
+            scenario_path: argument_value(arguments, "--scenario").cloned(),
+            script_path: argument_value(arguments, "--script").cloned(),
+            replay_path: argument_value(arguments, "--replay").cloned(),
+        }));
+    }
+}
+
+fn number_argument(arguments: &Vec<String>, flag: &str, default: usize) -> Result<usize, String> {
+    return match argument_value(arguments, flag) {
+        Some(value) => value.parse::<usize>().map_err(|_| format!("{} expects a whole number, got {}", flag, value)),
+        None => Ok(default),
+    };
+}
+
+// for counts and rates where 0 makes no sense, e.g. "--fps 0" would write a video no player accepts
+fn positive_number_argument(arguments: &Vec<String>, flag: &str, default: usize) -> Result<usize, String> {
+    return match number_argument(arguments, flag, default)? {
+        0 => Err(format!("{} expects a whole number of at least 1, got 0", flag)),
+        value => Ok(value),
+    };
+}
+
+fn video_options(arguments: &Vec<String>, path: &str) -> Result<VideoOptions, String> {
+    let size: Option<(usize, usize)> = match argument_value(arguments, "--video-size") {
+        Some(text) => Some(video::parse_size(text).map_err(|error| error.to_string())?),
+        None => None,
+    };
+
+    let frames_per_second: usize = positive_number_argument(arguments, "--fps", video::DEFAULT_FRAMES_PER_SECOND as usize)?;
+
+    return Ok(VideoOptions {
+        path: String::from(path),
+        every: positive_number_argument(arguments, "--video-every", 1)?,
+        size,
+        frames_per_second: u32::try_from(frames_per_second).map_err(|_| format!("--fps {} is too large", frames_per_second))?,
+        caption: argument_value(arguments, "--caption").cloned(),
+        timestamp: arguments.iter().any(|argument| argument == "--timestamp"),
+    });
+}
+
+pub fn run(mut state: State, options: &HeadlessOptions) -> Result<(), String> {
+    if let Some(path) = &options.replay_path {
+        return replay(state, path, options);
+    }
+
+    if let Some(path) = &options.scenario_path {
+        Scenario::load(path)
+            .and_then(|scenario| state.apply_scenario(scenario))
+            .map_err(|error| format!("Could not open {}: {}", path, error))?;
+    }
+
+    let mut recorder: Option<VideoRecorder> = match &options.video {
+        Some(video_options) => Some(start_video(video_options, state.width, state.height)?),
+        None => None,
+    };
+
+    let mut script: Option<Script> = match &options.script_path {
+        Some(path) => Some(Script::load(path, &mut state).map_err(|error| format!("Could not load script {}: {}", path, error))?),
+        None => None,
+    };
+
+    for _ in 0..options.steps {
+        state.progress_one_step();
+
+        let collisions: Vec<CollisionEvent> = state.take_collision_events();
+        state.take_sensor_events();
+
+        if let Some(script) = &mut script {
+            script.after_step(&mut state, &collisions).map_err(|error| error.to_string())?;
+        }
+
+        if let Some(recorder) = &mut recorder {
+            recorder
+                .capture(&state.get_rendered_view(), state.global_time)
+                .map_err(|error| format!("Could not write {}: {}", recorder.path(), error))?;
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        let path: String = String::from(recorder.path());
+        let frames: usize = recorder.finish().map_err(|error| format!("Could not finish {}: {}", path, error))?;
+        println!("Wrote {} frames to {}", frames, path);
+    }
+
+    println!(
+        "{} steps, {} circles, temperature {:.3}, total momentum {:.3}",
+        state.global_time,
+        state.circles.len(),
+        state.get_temperature(),
+        state.get_total_momentum(),
+    );
+
+    return Ok(());
+}
+
+// replays a recording with the same code as the window and fails on the first divergent tick
+fn replay(state: State, path: &str, options: &HeadlessOptions) -> Result<(), String> {
+    let mut session: Session = Session::new(state);
+    let mut interface: HeadlessInterface = HeadlessInterface;
+
+    session.start_replay(path).map_err(|error| format!("Could not replay {}: {}", path, error))?;
+
+    if let Some(video_options) = &options.video {
+        session.video = Some(start_video(video_options, session.world_state.width, session.world_state.height)?);
+    }
+
+    while session.replay.is_some() {
+        session.handle(Message::Tick, &mut interface);
+    }
+
+    session.stop_video(&mut interface);
+
+    return match session.finished_replay.as_ref().and_then(|replay| replay.first_divergent_tick()) {
+        Some(tick) => Err(format!("{} diverged at tick {}", path, tick)),
+        None => Ok(()),
+    };
+}
+
+fn start_video(options: &VideoOptions, world_width: f64, world_height: f64) -> Result<VideoRecorder, String> {
+    return VideoRecorder::start(options.clone(), world_width, world_height)
+        .map_err(|error| format!("Could not record video to {}: {}", options.path, error));
+}
@@ -0,0 +1,197 @@
+// Occupancy heatmap: how often circle centres have been in every cell of a grid over the world, accumulated
+// step by step while the heatmap is shown. It is drawn instead of the background, colour-mapped from the emptiest
+// to the most visited cell. Every step of global time is counted once: seeking through the history only restores
+// snapshots, and steps that are simulated again after going back (resuming or stepping forward from an earlier
+// snapshot) are skipped until the simulation passes the newest step already counted.
+
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circle::Circle,
+    common_structs::{RGBCanvas, RGBColor},
+    error::SimulationError,
+};
+
+// from empty to the most visited cell, roughly matplotlib's inferno
+const COLOR_MAP: [RGBColor; 5] = [
+    RGBColor { r: 0, g: 0, b: 4 },
+    RGBColor { r: 87, g: 16, b: 110 },
+    RGBColor { r: 188, g: 55, b: 84 },
+    RGBColor { r: 249, g: 142, b: 9 },
+    RGBColor { r: 252, g: 255, b: 164 },
+];
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HeatmapSettings {
+    pub enabled: bool,
+    pub cell_size: f64,
+    pub decay: f64, // fraction of the density lost every step, 0.0 keeps everything
+}
+
+impl Default for HeatmapSettings {
+    fn default() -> HeatmapSettings {
+        return HeatmapSettings {
+            enabled: false,
+            cell_size: 10.0,
+            decay: 0.0,
+        };
+    }
+}
+
+pub struct Heatmap {
+    cell_size: f64,
+    columns: usize,
+    rows: usize,
+    density: Vec<f64>, // row by row
+    counted_until: Option<usize>, // newest global time added
+}
+
+impl Heatmap {
+    pub fn new(width: f64, height: f64, cell_size: f64) -> Heatmap {
+        let columns: usize = f64::max((width / cell_size).ceil(), 1.0) as usize;
+        let rows: usize = f64::max((height / cell_size).ceil(), 1.0) as usize;
+
+        return Heatmap {
+            cell_size,
+            columns,
+            rows,
+            density: vec![0.0; columns * rows],
+            counted_until: None,
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.density.iter_mut().for_each(|value| *value = 0.0);
+        self.counted_until = None;
+    }
+
+    // one count per circle, in the cell holding its centre, for the circles as they are at global_time
+    pub fn accumulate(&mut self, circles: &[Circle], decay: f64, global_time: usize) {
+        if self.counted_until.is_some_and(|counted_until| global_time <= counted_until) {
+            return;
+        }
+
+        self.counted_until = Some(global_time);
+
+        if decay > 0.0 {
+            let kept: f64 = 1.0 - decay.min(1.0);
+            self.density.iter_mut().for_each(|value| *value *= kept);
+        }
+
+        for circle in circles.iter() {
+            let column: f64 = f64::clamp((circle.x_pos / self.cell_size).floor(), 0.0, (self.columns - 1) as f64);
+            let row: f64 = f64::clamp((circle.y_pos / self.cell_size).floor(), 0.0, (self.rows - 1) as f64);
+
+            self.density[row as usize * self.columns + column as usize] += 1.0;
+        }
+    }
+
+    // fills the whole canvas, interpolating between cell centres so that the cells do not show as blocks
+    pub fn put_on_canvas(&self, canvas: &mut RGBCanvas, scale: f64) {
+        let highest: f64 = self.density.iter().cloned().fold(0.0, f64::max);
+        let cell_pixels: f64 = self.cell_size * scale;
+
+        for j in 0..canvas.height {
+            let y: f64 = ((j as f64 + 0.5) / cell_pixels - 0.5).clamp(0.0, (self.rows - 1) as f64);
+            let row: usize = (y.floor() as usize).min(self.rows.saturating_sub(2));
+            let y_fraction: f64 = y - row as f64;
+            let next_row: usize = (row + 1).min(self.rows - 1);
+
+            for i in 0..canvas.width {
+                let x: f64 = ((i as f64 + 0.5) / cell_pixels - 0.5).clamp(0.0, (self.columns - 1) as f64);
+                let column: usize = (x.floor() as usize).min(self.columns.saturating_sub(2));
+                let x_fraction: f64 = x - column as f64;
+                let next_column: usize = (column + 1).min(self.columns - 1);
+
+                let top: f64 = self.density[row * self.columns + column] * (1.0 - x_fraction)
+                    + self.density[row * self.columns + next_column] * x_fraction;
+                let bottom: f64 = self.density[next_row * self.columns + column] * (1.0 - x_fraction)
+                    + self.density[next_row * self.columns + next_column] * x_fraction;
+                let value: f64 = top * (1.0 - y_fraction) + bottom * y_fraction;
+
+                let color: RGBColor = if highest > 0.0 {map_color(value / highest)} else {COLOR_MAP[0]};
+                let index: usize = (j * canvas.width + i) * 3;
+
+                canvas.data[index] = color.r;
+                canvas.data[index + 1] = color.g;
+                canvas.data[index + 2] = color.b;
+            }
+        }
+    }
+
+    // one line per row of cells, from the top of the world; values are the accumulated counts
+    pub fn save_csv(&self, path: &str) -> Result<(), SimulationError> {
+        let mut text: String = String::new();
+
+        for row in self.density.chunks(self.columns) {
+            let values: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            text.push_str(&values.join(","));
+            text.push('\n');
+        }
+
+        fs::write(path, text)?;
+
+        return Ok(());
+    }
+}
+
+// fraction from 0.0 to 1.0 to a colour, linear between the stops of the colour map
+fn map_color(fraction: f64) -> RGBColor {
+    let position: f64 = fraction.clamp(0.0, 1.0) * (COLOR_MAP.len() - 1) as f64;
+    let stop: usize = (position.floor() as usize).min(COLOR_MAP.len() - 2);
+    let t: f64 = position - stop as f64;
+
+    let mix = |from: u8, to: u8| -> u8 {
+        return (from as f64 + (to as f64 - from as f64) * t).round() as u8;
+    };
+
+    return RGBColor {
+        r: mix(COLOR_MAP[stop].r, COLOR_MAP[stop + 1].r),
+        g: mix(COLOR_MAP[stop].g, COLOR_MAP[stop + 1].g),
+        b: mix(COLOR_MAP[stop].b, COLOR_MAP[stop + 1].b),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::{History, HistoryMode};
+    use crate::state::State;
+
+    fn total(state: &State) -> f64 {
+        return state.heatmap.density.iter().sum();
+    }
+
+    #[test]
+    fn steps_simulated_again_after_going_back_are_counted_once() {
+        let mut state: State = State::new_with_seed(200, 200, 3);
+        state.heatmap_settings.enabled = true;
+        state.add_circle_at(50.0, 50.0, 2.0, 1.0, Some((10.0, 1.0))).unwrap();
+        state.add_circle_at(150.0, 120.0, -1.0, 2.0, Some((10.0, 1.0))).unwrap();
+
+        let mut history: History = History::new(100, HistoryMode::Full);
+
+        for _ in 0..20 {
+            history.tick(&mut state);
+        }
+
+        let counted: f64 = total(&state);
+
+        for _ in 0..10 {
+            history.step_back(&mut state);
+        }
+
+        history.resume();
+
+        for _ in 0..10 {
+            history.tick(&mut state);
+        }
+
+        assert_eq!(total(&state), counted);
+
+        history.tick(&mut state);
+
+        assert_eq!(total(&state), counted + 2.0);
+    }
+}
@@ -0,0 +1,425 @@
+// Ring buffer of past states, used to rewind, step and scrub through the simulation
+
+use std::collections::VecDeque;
+
+use rand_chacha::ChaCha8Rng;
+
+use crate::{circle::Circle, cluster::Cluster, state::State};
+
+// everything in State that changes while the simulation runs; settings and the background are not recorded
+#[derive(Clone)]
+pub struct StateSnapshot {
+    pub global_time: usize,
+    pub circles: Vec<Circle>,
+    pub clusters: Vec<Cluster>,
+    pub selected_circle_index: usize,
+    pub has_selected_circle: bool,
+    pub rng: ChaCha8Rng,
+    pub next_circle_id: usize,
+    pub next_cluster_id: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum HistoryMode {
+    Full,
+    // only circles and clusters that changed since the previous snapshot are kept, with a full snapshot every keyframe_interval;
+    // those that only moved keep just their position and velocity
+    Delta {
+        keyframe_interval: usize,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Playback {
+    Live,
+    Paused,
+    Rewinding,
+}
+
+// the fields that change every step a body moves, all else changes rarely
+trait Moving: Clone + PartialEq {
+    type Motion: Clone;
+
+    fn motion(&self) -> Self::Motion;
+    fn set_motion(&mut self, motion: &Self::Motion);
+}
+
+impl Moving for Circle {
+    type Motion = [f64; 4]; // x_pos, y_pos, x_vel, y_vel
+
+    fn motion(&self) -> [f64; 4] {
+        return [self.x_pos, self.y_pos, self.x_vel, self.y_vel];
+    }
+
+    fn set_motion(&mut self, motion: &[f64; 4]) {
+        [self.x_pos, self.y_pos, self.x_vel, self.y_vel] = *motion;
+    }
+}
+
+impl Moving for Cluster {
+    type Motion = [f64; 6]; // x_pos, y_pos, x_vel, y_vel, angle, angular_vel
+
+    fn motion(&self) -> [f64; 6] {
+        return [self.x_pos, self.y_pos, self.x_vel, self.y_vel, self.angle, self.angular_vel];
+    }
+
+    fn set_motion(&mut self, motion: &[f64; 6]) {
+        [self.x_pos, self.y_pos, self.x_vel, self.y_vel, self.angle, self.angular_vel] = *motion;
+    }
+}
+
+// differences from the previous list, both sorted by index
+struct Changes<T: Moving> {
+    count: usize,
+    moved: Vec<(usize, T::Motion)>, // only the motion differs
+    replaced: Vec<(usize, T)>, // anything else differs, or the item is new
+}
+
+enum HistoryEntry {
+    Full(StateSnapshot),
+    Delta {
+        header: StateSnapshot, // scalar fields only, circles and clusters are left empty
+        circles: Changes<Circle>,
+        clusters: Changes<Cluster>,
+    },
+}
+
+pub struct History {
+    pub capacity: usize,
+    pub mode: HistoryMode,
+    pub playback: Playback,
+    entries: VecDeque<HistoryEntry>,
+    cursor: usize, // index of the snapshot shown while not live
+    last_snapshot: Option<StateSnapshot>, // newest snapshot, the base for the next delta
+    entries_since_keyframe: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize, mode: HistoryMode) -> History {
+        return History {
+            capacity: usize::max(capacity, 1),
+            mode,
+            playback: Playback::Live,
+            entries: VecDeque::with_capacity(capacity),
+            cursor: 0,
+            last_snapshot: None,
+            entries_since_keyframe: 0,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    // index of the snapshot currently shown, the newest one while live
+    pub fn position(&self) -> usize {
+        if self.playback == Playback::Live {
+            return self.entries.len().saturating_sub(1);
+        }
+
+        return self.cursor;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor = 0;
+        self.last_snapshot = None;
+        self.entries_since_keyframe = 0;
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = usize::max(capacity, 1);
+
+        while self.entries.len() > self.capacity {
+            self.drop_oldest();
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: HistoryMode) {
+        self.mode = mode;
+        // next snapshot starts a fresh keyframe
+        self.entries_since_keyframe = usize::MAX;
+    }
+
+    pub fn record(&mut self, state: &State) {
+        if self.entries.len() >= self.capacity {
+            self.drop_oldest();
+        }
+
+        let snapshot: StateSnapshot = state.take_snapshot();
+
+        let entry: HistoryEntry = match (self.mode, &self.last_snapshot) {
+            (HistoryMode::Delta { keyframe_interval }, Some(previous)) if self.entries_since_keyframe < keyframe_interval => {
+                self.entries_since_keyframe += 1;
+                History::make_delta(previous, &snapshot)
+            }
+            _ => {
+                self.entries_since_keyframe = 1;
+                HistoryEntry::Full(snapshot.clone())
+            }
+        };
+
+        self.entries.push_back(entry);
+        self.last_snapshot = Some(snapshot);
+        self.cursor = self.entries.len() - 1;
+    }
+
+    // called once per tick instead of stepping the state directly
+    pub fn tick(&mut self, state: &mut State) {
+        match self.playback {
+            Playback::Live => {
+                if self.entries.is_empty() {
+                    self.record(state);
+                }
+
+                state.progress_one_step();
+                self.record(state);
+            }
+            Playback::Paused => {}
+            Playback::Rewinding => {
+                if self.cursor == 0 {
+                    self.playback = Playback::Paused;
+                } else {
+                    self.seek(self.cursor - 1, state);
+                    self.playback = Playback::Rewinding;
+                }
+            }
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if self.playback == Playback::Live {
+            self.cursor = self.entries.len().saturating_sub(1);
+        }
+
+        self.playback = Playback::Paused;
+    }
+
+    pub fn rewind(&mut self) {
+        self.pause();
+        self.playback = Playback::Rewinding;
+    }
+
+    pub fn step_back(&mut self, state: &mut State) {
+        self.pause();
+
+        if self.cursor > 0 {
+            self.seek(self.cursor - 1, state);
+        }
+    }
+
+    // moves forward through the recorded snapshots, or simulates a new step at the end of the buffer
+    pub fn step_forward(&mut self, state: &mut State) {
+        self.pause();
+
+        if self.cursor + 1 < self.entries.len() {
+            self.seek(self.cursor + 1, state);
+        } else {
+            self.truncate_after_cursor();
+            state.progress_one_step();
+            self.record(state);
+        }
+    }
+
+    pub fn seek(&mut self, index: usize, state: &mut State) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        self.pause();
+        self.cursor = usize::min(index, self.entries.len() - 1);
+        state.restore_snapshot(&self.snapshot_at(self.cursor));
+    }
+
+    // continues the live simulation from the snapshot shown, forgetting everything recorded after it
+    pub fn resume(&mut self) {
+        if self.playback != Playback::Live {
+            self.truncate_after_cursor();
+            self.playback = Playback::Live;
+        }
+    }
+
+    fn truncate_after_cursor(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        self.entries.truncate(self.cursor + 1);
+        self.last_snapshot = Some(self.snapshot_at(self.cursor));
+        // keyframe count is unknown after truncation, so the next snapshot is stored in full
+        self.entries_since_keyframe = usize::MAX;
+    }
+
+    fn drop_oldest(&mut self) {
+        // the next entry may depend on the oldest one, so it becomes a keyframe first
+        if self.entries.len() > 1 {
+            if let HistoryEntry::Delta { .. } = self.entries[1] {
+                let snapshot: StateSnapshot = self.snapshot_at(1);
+                self.entries[1] = HistoryEntry::Full(snapshot);
+            }
+        }
+
+        self.entries.pop_front();
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn snapshot_at(&self, index: usize) -> StateSnapshot {
+        let mut keyframe_index: usize = index;
+
+        while let HistoryEntry::Delta { .. } = self.entries[keyframe_index] {
+            keyframe_index -= 1;
+        }
+
+        let mut snapshot: StateSnapshot = match &self.entries[keyframe_index] {
+            HistoryEntry::Full(snapshot) => snapshot.clone(),
+            HistoryEntry::Delta { .. } => unreachable!(),
+        };
+
+        for k in (keyframe_index + 1)..(index + 1) {
+            if let HistoryEntry::Delta { header, circles, clusters } = &self.entries[k] {
+                let circles: Vec<Circle> = History::apply_changes(snapshot.circles, circles);
+                let clusters: Vec<Cluster> = History::apply_changes(snapshot.clusters, clusters);
+
+                snapshot = header.clone();
+                snapshot.circles = circles;
+                snapshot.clusters = clusters;
+            }
+        }
+
+        return snapshot;
+    }
+
+    fn make_delta(previous: &StateSnapshot, current: &StateSnapshot) -> HistoryEntry {
+        let mut header: StateSnapshot = current.clone();
+        header.circles = Vec::new();
+        header.clusters = Vec::new();
+
+        return HistoryEntry::Delta {
+            header,
+            circles: History::find_changes(&previous.circles, &current.circles),
+            clusters: History::find_changes(&previous.clusters, &current.clusters),
+        };
+    }
+
+    fn find_changes<T: Moving>(previous: &Vec<T>, current: &Vec<T>) -> Changes<T> {
+        let mut changes: Changes<T> = Changes {
+            count: current.len(),
+            moved: Vec::new(),
+            replaced: Vec::new(),
+        };
+
+        for (index, item) in current.iter().enumerate() {
+            if index >= previous.len() {
+                changes.replaced.push((index, item.clone()));
+            } else if previous[index] != *item {
+                let mut moved: T = previous[index].clone();
+                moved.set_motion(&item.motion());
+
+                if moved == *item {
+                    changes.moved.push((index, item.motion()));
+                } else {
+                    changes.replaced.push((index, item.clone()));
+                }
+            }
+        }
+
+        return changes;
+    }
+
+    fn apply_changes<T: Moving>(mut items: Vec<T>, changes: &Changes<T>) -> Vec<T> {
+        items.truncate(changes.count);
+
+        // only items that were there before can have moved
+        for (index, motion) in changes.moved.iter() {
+            items[*index].set_motion(motion);
+        }
+
+        // sorted by index, so anything past the end is appended in order
+        for (index, item) in changes.replaced.iter() {
+            if *index < items.len() {
+                items[*index] = item.clone();
+            } else {
+                items.push(item.clone());
+            }
+        }
+
+        return items;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    // what an entry keeps inline, names and cluster member lists are left out
+    fn stored_size(entry: &HistoryEntry) -> usize {
+        return match entry {
+            HistoryEntry::Full(snapshot) => {
+                size_of::<StateSnapshot>() + snapshot.circles.len() * size_of::<Circle>() + snapshot.clusters.len() * size_of::<Cluster>()
+            }
+            HistoryEntry::Delta { circles, clusters, .. } => {
+                size_of::<StateSnapshot>()
+                    + circles.moved.len() * size_of::<(usize, [f64; 4])>()
+                    + circles.replaced.len() * size_of::<(usize, Circle)>()
+                    + clusters.moved.len() * size_of::<(usize, [f64; 6])>()
+                    + clusters.replaced.len() * size_of::<(usize, Cluster)>()
+            }
+        };
+    }
+
+    // a hundred circles at rest and three moving ones
+    fn mostly_static_state() -> State {
+        let mut state: State = State::new_with_seed(400, 400, 2);
+
+        for i in 0..100 {
+            state.add_circle_at(20.0 + 36.0 * (i % 10) as f64, 20.0 + 36.0 * (i / 10) as f64, 0.0, 0.0, Some((6.0, 1.0))).unwrap();
+        }
+
+        // in a row of their own, far enough apart never to collide
+        for i in 0..3 {
+            state.add_circle_at(50.0 + 100.0 * i as f64, 380.0, 1.0, 0.0, Some((6.0, 1.0))).unwrap();
+        }
+
+        return state;
+    }
+
+    fn run(mode: HistoryMode, steps: usize) -> (History, Vec<StateSnapshot>) {
+        let mut state: State = mostly_static_state();
+        let mut history: History = History::new(steps + 1, mode);
+        let mut snapshots: Vec<StateSnapshot> = Vec::new();
+
+        for _ in 0..steps {
+            history.tick(&mut state);
+            snapshots.push(state.take_snapshot());
+        }
+
+        return (history, snapshots);
+    }
+
+    #[test]
+    fn moving_circles_keep_only_their_motion() {
+        let (delta_history, snapshots) = run(HistoryMode::Delta { keyframe_interval: 60 }, 50);
+        let (full_history, _) = run(HistoryMode::Full, 50);
+
+        let delta_size: usize = delta_history.entries.iter().map(stored_size).sum();
+        let full_size: usize = full_history.entries.iter().map(stored_size).sum();
+
+        assert!(delta_size * 10 < full_size, "delta {} bytes, full {} bytes", delta_size, full_size);
+
+        for entry in delta_history.entries.iter().skip(1) {
+            if let HistoryEntry::Delta { circles, .. } = entry {
+                assert_eq!(circles.moved.len(), 3);
+                assert!(circles.replaced.is_empty());
+            }
+        }
+
+        // the first entry is the state before the first step
+        for (index, snapshot) in snapshots.iter().enumerate() {
+            let restored: StateSnapshot = delta_history.snapshot_at(index + 1);
+
+            assert!(restored.circles == snapshot.circles);
+            assert_eq!(restored.global_time, snapshot.global_time);
+        }
+    }
+}
@@ -1,23 +1,58 @@
-use common_structs::{Coord, RGBCanvas};
+use background::BackgroundFit;
+use circle::CircleLabel;
+use common_structs::{BlendMode, RGBCanvas};
+use debug_draw::DebugLayer;
 use fltk::{
-    app::{self, App, MouseButton},
+    app::{self, App},
     enums::{self, Color, ColorDepth, Event, FrameType},
     image::RgbImage,
     prelude::*,
     *,
 };
 
+use error::SimulationError;
+use flow_field::FlowField;
+use headless::HeadlessOptions;
+use sensor::SensorKind;
+use session::{Message, Question, Session, UserInterface};
 use state::State;
+use thermostat::Thermostat;
+use trails::TrailStyle;
 
 mod common_structs;
 mod state;
 mod circle;
+mod cluster;
+mod fluid;
+mod broadphase;
+mod sph;
+mod flow_field;
+mod thermostat;
+mod history;
+mod reversal;
+mod events;
+mod sensor;
+mod scripting;
+mod headless;
+mod scenario;
+mod error;
+mod drawing;
+mod text;
+mod debug_draw;
+mod trails;
+mod heatmap;
+mod background;
+mod recording;
+mod session;
+mod video;
+mod export;
+mod svg;
 
 const WIND_LABEL: &str = "Floating Objects";
 // const WIND_WIDTH: i32 = 1820;
 const WIND_WIDTH: i32 = 800;
 // const WIND_HEIGHT: i32 = 1000;
-const WIND_HEIGHT: i32 = 600;
+const WIND_HEIGHT: i32 = 680;
 // const MAIN_IMAGE_WIDTH: i32 = 940;
 // const MAIN_IMAGE_WIDTH: i32 = 1560;
 const MAIN_IMAGE_WIDTH: i32 = 512;
@@ -27,37 +62,74 @@ const MAIN_IMAGE_FRAME_THICKNESS: i32 = 4;
 const MAIN_IMAGE_X_POS: i32 = 10;
 const MAIN_IMAGE_Y_POS: i32 = 10;
 const MENU_HEIGHT: i32 = 32;
+const MAX_TEMPERATURE: f64 = 2000.0;
 
-#[derive(Clone)]
-enum Message {
-    Quit,
-    AddCircleButEv,
-    RemoveCircleButEv,
-    WBev,
-    BBev,
-    GBev,
-    LGBev,
-    MouseDown(i32, i32, MouseButton),
-    MouseDrag(i32, i32),
-    MouseMove(i32, i32),
-    MouseReleased(i32, i32, MouseButton),
-    Tick,
+// what the session asks of the window: dialogs and the values of widgets
+struct GuiInterface {
+    wind: window::Window,
+    thermostat_choice: menu::Choice,
+    target_temperature_slider: valuator::HorValueSlider,
+    timeline_slider: valuator::HorNiceSlider,
 }
 
-enum Colour {
-    Black,
-    Grey,
-    LightGrey,
-    White,
+impl UserInterface for GuiInterface {
+    fn answer(&mut self, question: &Question) -> Option<String> {
+        return match question {
+            Question::Text { prompt, default } => dialog::input_default(prompt, default),
+            Question::File { title, pattern } => dialog::file_chooser(title, pattern, ".", false),
+            Question::NewFile { title, pattern, default_name } => dialog::file_chooser(title, pattern, default_name, false),
+            Question::ThermostatChoice => Some(self.thermostat_choice.value().to_string()),
+            Question::TargetTemperature => Some(self.target_temperature_slider.value().to_string()),
+            Question::TimelinePosition => Some((self.timeline_slider.value() as usize).to_string()),
+            // cancelling gives back the default, which is then no change either
+            Question::Colour { title, default } => {
+                let (r, g, b) = dialog::color_chooser_with_default(title, dialog::ColorMode::Rgb, (default.r, default.g, default.b));
+
+                if (r, g, b) == (default.r, default.g, default.b) {
+                    None
+                } else {
+                    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+                }
+            }
+        };
+    }
+
+    fn alert(&mut self, message: &str) {
+        dialog::alert_default(message);
+    }
+
+    fn inform(&mut self, message: &str) {
+        dialog::message_default(message);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.wind.set_label(&format!("{} - {}", WIND_LABEL, title));
+    }
+
+    fn quit(&mut self) {
+        fltk::app::quit();
+    }
 }
 
 fn main() {
-    let mut world_state: State = state::State::new(MAIN_IMAGE_WIDTH, MAIN_IMAGE_HEIGHT);
+    let world_state: State = match parse_seed_argument() {
+        Some(seed) => state::State::new_with_seed(MAIN_IMAGE_WIDTH, MAIN_IMAGE_HEIGHT, seed),
+        None => state::State::new(MAIN_IMAGE_WIDTH, MAIN_IMAGE_HEIGHT),
+    };
+    println!("Random seed is {}", world_state.seed);
+
+    if let Some(options) = HeadlessOptions::from_arguments(&std::env::args().collect()) {
+        if let Err(message) = options.and_then(|options| headless::run(world_state, &options)) {
+            println!("{}", message);
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    let mut session: Session = Session::new(world_state);
 
     let application: App = app::App::default();
-    let mut is_added_circle: bool = false;
-    let mut new_circle_coord: Coord = Coord::new(0.0, 0.0);
-    let mut cursor_coord: Coord = Coord::new(0.0, 0.0);
 
     let (s, r) = app::channel();
 
@@ -67,6 +139,86 @@ fn main() {
     menu.set_frame(enums::FrameType::FlatBox);
     menu.set_color(enums::Color::Light2);
 
+    menu.add_emit(
+        "&File/Open...\t",
+        enums::Shortcut::Ctrl | 'o',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::OpenScenario,
+    );
+
+    menu.add_emit(
+        "&File/Save\t",
+        enums::Shortcut::Ctrl | 's',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::SaveScenario,
+    );
+
+    menu.add_emit(
+        "&File/Save As...\t",
+        enums::Shortcut::Ctrl | enums::Shortcut::Shift | 's',
+        menu::MenuFlag::MenuDivider,
+        s.clone(),
+        Message::SaveScenarioAs,
+    );
+
+    menu.add_emit(
+        "&File/Load script...\t",
+        enums::Shortcut::Ctrl | enums::Shortcut::Shift | 'l',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::LoadScript,
+    );
+
+    menu.add_emit(
+        "&File/Unload script\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::MenuDivider,
+        s.clone(),
+        Message::UnloadScript,
+    );
+
+    menu.add_emit(
+        "&File/Export Image...\t",
+        enums::Shortcut::Ctrl | 'i',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::ExportImage,
+    );
+
+    menu.add_emit(
+        "&File/Export SVG...\t",
+        enums::Shortcut::Ctrl | enums::Shortcut::Shift | 'i',
+        menu::MenuFlag::MenuDivider,
+        s.clone(),
+        Message::ExportSvg,
+    );
+
+    menu.add_emit(
+        "&File/Start recording\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::StartRecording,
+    );
+
+    menu.add_emit(
+        "&File/Stop recording...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::StopRecording,
+    );
+
+    menu.add_emit(
+        "&File/Replay recording...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::MenuDivider,
+        s.clone(),
+        Message::ReplayRecording,
+    );
+
     menu.add_emit(
         "&File/Quit\t",
         enums::Shortcut::Ctrl | 'q',
@@ -75,6 +227,422 @@ fn main() {
         Message::Quit,
     );
 
+    menu.add_emit(
+        "&Simulation/Sticky collisions\t",
+        enums::Shortcut::Ctrl | 'k',
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::StickyCollisionsToggled,
+    );
+
+    menu.add_emit(
+        "&Simulation/Random walk\t",
+        enums::Shortcut::Ctrl | 'w',
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::RandomWalkToggled,
+    );
+
+    menu.add_emit(
+        "&Simulation/Add seed circle\t",
+        enums::Shortcut::Ctrl | 'e',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::AddSeedCircle,
+    );
+
+    menu.add_emit(
+        "&Simulation/Restart with random seed...\t",
+        enums::Shortcut::Ctrl | 'r',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::RestartWithSeed,
+    );
+
+    menu.add_emit(
+        "&Simulation/Reverse time\t",
+        enums::Shortcut::Ctrl | 'b',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::ReverseTime,
+    );
+
+    menu.add_emit(
+        "&Simulation/Test reversibility...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::TestReversibility,
+    );
+
+    menu.add_emit(
+        "&History/Buffer size...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::HistoryCapacity,
+    );
+
+    menu.add_emit(
+        "&History/Delta compression\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle | menu::MenuFlag::Value,
+        s.clone(),
+        Message::HistoryDeltaToggled,
+    );
+
+    menu.add_emit(
+        "&Simulation/Gravity\t",
+        enums::Shortcut::Ctrl | 'g',
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::GravityToggled,
+    );
+
+    menu.add_emit(
+        "&Simulation/Water\t",
+        enums::Shortcut::Ctrl | 'l',
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::WaterToggled,
+    );
+
+    menu.add_emit(
+        "&Simulation/Pour SPH fluid\t",
+        enums::Shortcut::Ctrl | 'p',
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::PourFluid,
+    );
+
+    menu.add_emit(
+        "&View/Fluid as surface\t",
+        enums::Shortcut::Ctrl | 'f',
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::FluidSurfaceToggled,
+    );
+
+    menu.add_emit(
+        "&Simulation/Wind/Off\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::WindOff,
+    );
+
+    menu.add_emit(
+        "&Simulation/Wind/Uniform\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::WindUniform,
+    );
+
+    menu.add_emit(
+        "&Simulation/Wind/Vortex\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::WindVortex,
+    );
+
+    menu.add_emit(
+        "&Simulation/Wind/Shear\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::WindShear,
+    );
+
+    menu.add_emit(
+        "&Simulation/Wind/From file...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::WindFromFile,
+    );
+
+    menu.add_emit(
+        "&View/Flow field\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::FlowFieldToggled,
+    );
+
+    menu.add_emit(
+        "&Circle/Rename...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::RenameCircle,
+    );
+
+    menu.add_emit(
+        "&Circle/Opacity...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::CircleOpacity,
+    );
+
+    menu.add_emit(
+        "&Circle/Blend mode/Normal\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::CircleBlendMode(BlendMode::Normal),
+    );
+
+    menu.add_emit(
+        "&Circle/Blend mode/Additive\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::CircleBlendMode(BlendMode::Additive),
+    );
+
+    menu.add_emit(
+        "&Circle/Blend mode/Multiply\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::CircleBlendMode(BlendMode::Multiply),
+    );
+
+    menu.add_emit(
+        "&Circle/Leave trail\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::CircleTrailToggled,
+    );
+
+    menu.add_emit(
+        "&Sensors/Add rectangle\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::AddSensor(SensorKind::Rectangle),
+    );
+
+    menu.add_emit(
+        "&Sensors/Add circle\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::AddSensor(SensorKind::Circle),
+    );
+
+    menu.add_emit(
+        "&Sensors/Add line\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::AddSensor(SensorKind::Line),
+    );
+
+    menu.add_emit(
+        "&Sensors/Reset counters\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::ResetSensorCounters,
+    );
+
+    menu.add_emit(
+        "&Sensors/Remove all\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::RemoveSensors,
+    );
+
+    menu.add_emit(
+        "&View/Labels/Off\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::CircleLabelsChanged(CircleLabel::Off),
+    );
+
+    menu.add_emit(
+        "&View/Labels/Names\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::CircleLabelsChanged(CircleLabel::Name),
+    );
+
+    menu.add_emit(
+        "&View/Labels/Masses\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::CircleLabelsChanged(CircleLabel::Mass),
+    );
+
+    menu.add_emit(
+        "&View/Labels/Speeds\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::CircleLabelsChanged(CircleLabel::Speed),
+    );
+
+    menu.add_emit(
+        "&View/Trails/Show\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::TrailsToggled,
+    );
+
+    menu.add_emit(
+        "&View/Trails/Lines\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::TrailStyleChanged(TrailStyle::Line),
+    );
+
+    menu.add_emit(
+        "&View/Trails/Dots\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Radio,
+        s.clone(),
+        Message::TrailStyleChanged(TrailStyle::Dots),
+    );
+
+    menu.add_emit(
+        "&View/Trails/Length...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::TrailLength,
+    );
+
+    menu.add_emit(
+        "&View/Heatmap/Show\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::HeatmapToggled,
+    );
+
+    menu.add_emit(
+        "&View/Heatmap/Reset\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::ResetHeatmap,
+    );
+
+    menu.add_emit(
+        "&View/Heatmap/Cell size...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::HeatmapCellSize,
+    );
+
+    menu.add_emit(
+        "&View/Heatmap/Decay...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::HeatmapDecay,
+    );
+
+    menu.add_emit(
+        "&View/Heatmap/Export CSV...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::ExportHeatmapCsv,
+    );
+
+    menu.add_emit(
+        "&View/Background/Colour...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::BackgroundColour,
+    );
+
+    menu.add_emit(
+        "&View/Background/Image, scaled...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::LoadBackgroundImage(BackgroundFit::Scaled),
+    );
+
+    menu.add_emit(
+        "&View/Background/Image, tiled...\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Normal,
+        s.clone(),
+        Message::LoadBackgroundImage(BackgroundFit::Tiled),
+    );
+
+    menu.add_emit(
+        "&View/Debug/Velocities\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::DebugLayerToggled(DebugLayer::Velocities),
+    );
+
+    menu.add_emit(
+        "&View/Debug/Contacts\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::DebugLayerToggled(DebugLayer::Contacts),
+    );
+
+    menu.add_emit(
+        "&View/Debug/Bounding boxes\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::DebugLayerToggled(DebugLayer::BoundingBoxes),
+    );
+
+    menu.add_emit(
+        "&View/Debug/Broadphase grid\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::DebugLayerToggled(DebugLayer::BroadphaseGrid),
+    );
+
+    menu.add_emit(
+        "&View/Debug/Centre of mass\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::DebugLayerToggled(DebugLayer::CentreOfMass),
+    );
+
+    menu.add_emit(
+        "&View/Debug/Time of impact\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::DebugLayerToggled(DebugLayer::TimeOfImpact),
+    );
+
+    menu.add_emit(
+        "&View/Log collisions\t",
+        enums::Shortcut::None,
+        menu::MenuFlag::Toggle,
+        s.clone(),
+        Message::LogCollisionsToggled,
+    );
+
     let mut framing_frame = frame::Frame::default()
         .with_pos(MAIN_IMAGE_X_POS, MAIN_IMAGE_Y_POS + MENU_HEIGHT)
         .with_size(
@@ -174,6 +742,117 @@ fn main() {
         .with_size(200, 40)
         .with_label("");
 
+    let mut temperature_display_frame = frame::Frame::default()
+        .with_pos(
+            MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20,
+            MENU_HEIGHT + MAIN_IMAGE_Y_POS + 250,
+        )
+        .with_size(200, 40)
+        .with_label("");
+
+    let mut thermostat_choice = menu::Choice::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 300,
+        200,
+        30,
+        "",
+    );
+    thermostat_choice.add_choice("Thermostat off|Velocity rescaling|Berendsen|Andersen");
+    thermostat_choice.set_value(0);
+    thermostat_choice.emit(s.clone(), Message::ThermostatChanged);
+
+    let mut target_temperature_slider = valuator::HorValueSlider::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 340,
+        200,
+        25,
+        "",
+    );
+    target_temperature_slider.set_range(0.0, MAX_TEMPERATURE);
+    target_temperature_slider.set_step(10.0, 1);
+    target_temperature_slider.set_value(session.world_state.target_temperature);
+    target_temperature_slider.emit(s.clone(), Message::TargetTemperatureChanged);
+
+    let mut b_thermalise = button::Button::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 375,
+        200,
+        40,
+        "Thermalise velocities",
+    );
+    b_thermalise.emit(s.clone(), Message::ThermaliseButEv);
+
+    let mut b_rewind = button::Button::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 425,
+        40,
+        40,
+        "@<<",
+    );
+    b_rewind.set_tooltip("Rewind");
+    b_rewind.emit(s.clone(), Message::RewindButEv);
+
+    let mut b_step_back = button::Button::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20 + (40 + 13) * 1,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 425,
+        40,
+        40,
+        "@|<",
+    );
+    b_step_back.set_tooltip("Step back one tick");
+    b_step_back.emit(s.clone(), Message::StepBackButEv);
+
+    let mut b_resume = button::Button::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20 + (40 + 13) * 2,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 425,
+        40,
+        40,
+        "@>",
+    );
+    b_resume.set_tooltip("Resume live simulation from here");
+    b_resume.emit(s.clone(), Message::ResumeButEv);
+
+    let mut b_step_forward = button::Button::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20 + (40 + 13) * 3,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 425,
+        40,
+        40,
+        "@>|",
+    );
+    b_step_forward.set_tooltip("Step forward one tick");
+    b_step_forward.emit(s.clone(), Message::StepForwardButEv);
+
+    let mut timeline_slider = valuator::HorNiceSlider::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 475,
+        145,
+        25,
+        "",
+    );
+    timeline_slider.set_step(1.0, 1);
+    timeline_slider.emit(s.clone(), Message::TimelineMoved);
+
+    let mut b_record_video = button::ToggleButton::new(
+        MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20 + 155,
+        MENU_HEIGHT + MAIN_IMAGE_Y_POS + 475,
+        45,
+        25,
+        "Rec",
+    );
+    b_record_video.set_tooltip("Record video of the simulation");
+    b_record_video.set_selection_color(Color::Red);
+    b_record_video.emit(s.clone(), Message::VideoRecordingToggled);
+
+    let mut sensor_display_frame = frame::Frame::default()
+        .with_pos(
+            MAIN_IMAGE_WIDTH + MAIN_IMAGE_X_POS + 20,
+            MENU_HEIGHT + MAIN_IMAGE_Y_POS + 510,
+        )
+        .with_size(200, 100)
+        .with_label("");
+    sensor_display_frame.set_label_size(12);
+    sensor_display_frame.set_align(enums::Align::Inside | enums::Align::TopLeft);
+
     wind.end();
     wind.show();
 
@@ -227,100 +906,41 @@ fn main() {
 
     app::add_timeout3(0.033, callback);
 
+    let mut gui: GuiInterface = GuiInterface {
+        wind: wind.clone(),
+        thermostat_choice: thermostat_choice.clone(),
+        target_temperature_slider: target_temperature_slider.clone(),
+        timeline_slider: timeline_slider.clone(),
+    };
+
     while application.wait() {
         if let Some(msg) = r.recv() {
-            match msg {
-                Message::Quit => {
-                    println!("quitting the app...");
-                    fltk::app::quit();
-                }
-                Message::AddCircleButEv => {
-                    println!("Adding circle...");
+            let is_tick: bool = matches!(msg, Message::Tick);
+            let may_load_scenario: bool = matches!(msg, Message::OpenScenario | Message::ReplayRecording);
 
-                    world_state.add_random_circle_at_coords(MAIN_IMAGE_WIDTH / 2, MAIN_IMAGE_HEIGHT / 2, 0.0, 0.0);
-                }
-                Message::RemoveCircleButEv => {
-                    println!("Removing circle...");
+            session.handle(msg, &mut gui);
 
-                    world_state.circles = Vec::new();
-                }
-                Message::WBev => {
-                    println!("Change background to White.");
-                    world_state.replace_background(
-                        generate_image_background(MAIN_IMAGE_WIDTH, MAIN_IMAGE_HEIGHT, Colour::White)
-                    );
-                }
-                Message::LGBev => {
-                    println!("Change background to Light Grey.");
-                    world_state.replace_background(
-                        generate_image_background(MAIN_IMAGE_WIDTH, MAIN_IMAGE_HEIGHT, Colour::LightGrey)
-                    );
-                }
-                Message::GBev => {
-                    println!("Change background to Grey.");
-                    world_state.replace_background(
-                        generate_image_background(MAIN_IMAGE_WIDTH, MAIN_IMAGE_HEIGHT, Colour::Grey)
-                    );
-                }
-                Message::BBev => {
-                    println!("Change background to Black.");
-                    world_state.replace_background(
-                        generate_image_background(MAIN_IMAGE_WIDTH, MAIN_IMAGE_HEIGHT, Colour::Black)
-                    );
-                }
-                Message::Tick => {
-                    redraw_image(
-                        &mut world_state,
-                        &mut image_frame,
-                        is_added_circle,
-                        &new_circle_coord,
-                        &cursor_coord,
-                    );
-                    
-                    _total_momentum_display_frame.set_label(&(String::from("Total momentum is:\n") + &format!("{:>10.3}", world_state.get_total_momentum())));
-                }
-                Message::MouseDown(x, y, button) => {
-                    println!("The image was clicked at coordinates x={}, y={}", x, y);
-
-                    if button == MouseButton::Right {
-                        world_state.select_circle(x, y);
-                    }
-                    
-                    if button == MouseButton::Left {
-                        if !is_added_circle {
-                            world_state.add_random_circle_at_coords(x, y, 0.0, 0.0);
-                            new_circle_coord.set_i(x, y);
-                            cursor_coord.set_i(x, y);
-                            is_added_circle = true;
-                        } else {
-                            // let new_coord: Coord = Coord::new_i(x, y);
-                            cursor_coord.set_i(x, y);
-                            let index: usize = world_state.circles.len()-1;
-
-                            world_state.circles[index].x_vel = (cursor_coord.x - new_circle_coord.x) / 100.0;
-                            world_state.circles[index].y_vel = (cursor_coord.y - new_circle_coord.y) / 100.0;
-                            is_added_circle = false;
-                        }
-                    }
-                }
-                Message::MouseDrag(x, y) => {
-                    let circle_index: usize = world_state.selected_circle_index;
+            // the recording may have been cancelled or have failed
+            b_record_video.set_value(session.video.is_some());
 
-                    if world_state.has_selected_circle {
-                        world_state.circles[circle_index].accelerate_to_position(x as f64, y as f64);
-                    }
-                }
-                Message::MouseMove(x, y) => {
-                    // println!("There was Move event at coordinates x={}, y={}", x, y);
-                    if is_added_circle {
-                        // draw a line from circle center to current mouse cursor
-                        cursor_coord.set_i(x, y);
-                    }
-                } 
-                _ => {
-                    println!("yet undefined event");
+            // a loaded scenario brings its own settings, which the widgets would otherwise overwrite on their next change
+            if may_load_scenario {
+                show_settings(&session.world_state, &mut menu, &mut thermostat_choice, &mut target_temperature_slider);
+            }
+
+            if is_tick {
+                if let Err(error) = redraw_image(&session.rendered_view(1), &mut image_frame) {
+                    println!("{}", error);
                 }
-            };
+
+                _total_momentum_display_frame.set_label(&format!("Total momentum is:\n{:>10.3}", session.world_state.get_total_momentum()));
+                timeline_slider.set_range(0.0, session.history.len().saturating_sub(1) as f64);
+                timeline_slider.set_value(session.history.position() as f64);
+                temperature_display_frame.set_label(&format!("Temperature is:\n{:>10.3}", session.world_state.get_temperature()));
+
+                let sensor_lines: Vec<String> = session.world_state.sensors.iter().map(|sensor| sensor.counter_summary()).collect();
+                sensor_display_frame.set_label(&sensor_lines.join("\n"));
+            }
         }
     }
 
@@ -328,77 +948,79 @@ fn main() {
 }
 
 
-fn redraw_image(world_state: &mut State, image_frame: &mut frame::Frame, is_line: bool, start: &Coord, end: &Coord) {
-    world_state.progress_one_step();
-    let mut image_data = world_state.get_rendered_view();
-    if is_line {
-        draw_line(&mut image_data, start, end);
+// reads "--seed <number>" from the command line
+fn parse_seed_argument() -> Option<u64> {
+    let arguments: Vec<String> = std::env::args().collect();
+
+    for i in 1..arguments.len() {
+        if arguments[i] == "--seed" {
+            match arguments.get(i + 1).map(|value| value.parse::<u64>()) {
+                Some(Ok(seed)) => return Some(seed),
+                _ => {
+                    println!("--seed expects a non-negative whole number, using a random seed instead");
+                    return None;
+                }
+            }
+        }
     }
 
+    return None;
+}
+
+fn redraw_image(image_data: &RGBCanvas, image_frame: &mut frame::Frame) -> Result<(), SimulationError> {
     let image = RgbImage::new(
         &image_data.data,
         image_data.width as i32,
         image_data.height as i32,
         ColorDepth::Rgb8,
-    )
-    .unwrap();
+    )?;
     image_frame.set_image(Some(image));
     image_frame.redraw();
-}
-
-fn generate_image_background(width: i32, height: i32, colour: Colour) -> Vec<u8> {
-    let num_pix: usize = (width * height) as usize;
 
-    let data_array: Vec<u8>;
-
-    match colour {
-        Colour::Black => data_array = vec![0; num_pix * 3],
-        Colour::Grey => data_array = vec![127; num_pix * 3],
-        Colour::LightGrey => data_array = vec![191; num_pix * 3],
-        Colour::White => data_array = vec![255; num_pix * 3],
-    }
-
-    return data_array;
+    return Ok(());
 }
 
-fn draw_line(image_data: &mut RGBCanvas, start: &Coord, end: &Coord) {
-    let width: i32 = image_data.width as i32;
-    
-    let x_s: i32 = start.x as i32;
-    let y_s: i32 = start.y as i32;
-    
-    let delta_x: f64 = end.x - start.x;
-    let delta_y: f64 = end.y - start.y;
-    let t: f64;
-
-    let step_x: f64;
-    let step_y: f64;
-
-    let mut x: i32;
-    let mut y: i32;
-
-    if delta_x > 0.0 && delta_y > 0.0 {
-        t = delta_x + delta_y;
-    } else if delta_x > 0.0 && delta_y < 0.0 {
-        t = delta_x - delta_y;
-    } else if delta_x < 0.0 && delta_y > 0.0 {
-        t = - delta_x + delta_y;
-    } else if delta_x < 0.0 && delta_y < 0.0 {
-        t = - delta_x - delta_y;
-    } else {
-        t = 1.0;
-    }
-
-    step_x = delta_x / t;
-    step_y = delta_y / t;
-
-    for t in 0..(t as usize + 1) {
-        x = x_s + (t as f64 * step_x) as i32;
-        y = y_s + (t as f64 * step_y) as i32;
+// checks the menu items and sets the widgets to the settings of the state
+fn show_settings(state: &State, menu: &mut menu::SysMenuBar, thermostat_choice: &mut menu::Choice, target_temperature_slider: &mut valuator::HorValueSlider) {
+    let checks: [(&str, bool); 19] = [
+        ("&Simulation/Sticky collisions\t", state.sticky_collisions),
+        ("&Simulation/Random walk\t", state.random_walk_step > 0.0),
+        ("&Simulation/Gravity\t", state.gravity != 0.0),
+        ("&Simulation/Water\t", !state.fluid_regions.is_empty()),
+        ("&View/Fluid as surface\t", state.render_fluid_as_surface),
+        ("&Simulation/Wind/Off\t", state.flow_field.is_none()),
+        ("&Simulation/Wind/Uniform\t", matches!(state.flow_field, Some(FlowField::Uniform { .. }))),
+        ("&Simulation/Wind/Vortex\t", matches!(state.flow_field, Some(FlowField::Vortex { .. }))),
+        ("&Simulation/Wind/Shear\t", matches!(state.flow_field, Some(FlowField::Shear { .. }))),
+        ("&Simulation/Wind/From file...\t", matches!(state.flow_field, Some(FlowField::Grid { .. }))),
+        ("&View/Flow field\t", state.show_flow_field),
+        ("&View/Labels/Off\t", state.circle_labels == CircleLabel::Off),
+        ("&View/Labels/Names\t", state.circle_labels == CircleLabel::Name),
+        ("&View/Labels/Masses\t", state.circle_labels == CircleLabel::Mass),
+        ("&View/Labels/Speeds\t", state.circle_labels == CircleLabel::Speed),
+        ("&View/Trails/Show\t", state.trail_settings.enabled),
+        ("&View/Trails/Lines\t", state.trail_settings.style == TrailStyle::Line),
+        ("&View/Trails/Dots\t", state.trail_settings.style == TrailStyle::Dots),
+        ("&View/Heatmap/Show\t", state.heatmap_settings.enabled),
+    ];
 
-        image_data.data[(y * width * 3 + x * 3 + 0) as usize] = 255;
-        image_data.data[(y * width * 3 + x * 3 + 1) as usize] = 255;
-        image_data.data[(y * width * 3 + x * 3 + 2) as usize] = 255;
+    for (path, checked) in checks {
+        if let Some(mut item) = menu.find_item(path) {
+            if checked {
+                item.set();
+            } else {
+                item.clear();
+            }
+        }
     }
 
+    let thermostat_index: i32 = match state.thermostat {
+        Thermostat::Off => 0,
+        Thermostat::VelocityRescaling => 1,
+        Thermostat::Berendsen { .. } => 2,
+        Thermostat::Andersen { .. } => 3,
+    };
+
+    thermostat_choice.set_value(thermostat_index);
+    target_temperature_slider.set_value(state.target_temperature);
 }
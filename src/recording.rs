@@ -0,0 +1,236 @@
+// Recordings of whole sessions: the world at the start, every message handled with the tick it arrived in
+// and the answers given to dialogs, plus a checksum of the state after every tick to spot divergent replays
+
+use std::fs;
+
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::SimulationError, scenario::Scenario, session::Message, state::State};
+
+pub const RECORDING_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub tick: usize, // number of ticks handled before the message arrived
+    pub message: Message,
+    pub answers: Vec<Option<String>>, // what the dialogs and widgets returned, in the order they were asked
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub initial: Scenario, // includes the seed and position of the random generator
+    pub inputs: Vec<RecordedInput>,
+    pub checksums: Vec<u64>, // State::checksum after each tick, the first one is tick 1
+}
+
+pub struct Replay {
+    recording: Recording,
+    next_input: usize,
+    first_divergence: Option<(usize, u64, u64)>, // tick, recorded and replayed checksum
+}
+
+impl Recording {
+    pub fn start(state: &State) -> Recording {
+        return Recording {
+            version: RECORDING_VERSION,
+            initial: state.to_scenario(),
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+        };
+    }
+
+    pub fn record(&mut self, tick: usize, message: Message, answers: Vec<Option<String>>) {
+        self.inputs.push(RecordedInput { tick, message, answers });
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), SimulationError> {
+        let text: String = ron::ser::to_string_pretty(self, PrettyConfig::default())?;
+        fs::write(path, text)?;
+
+        return Ok(());
+    }
+
+    pub fn load(path: &str) -> Result<Recording, SimulationError> {
+        let text: String = fs::read_to_string(path)?;
+        let recording: Recording = ron::from_str(&text)
+            .map_err(|error| SimulationError::Recording(format!("Not a recording: {}", error)))?;
+
+        if recording.version != RECORDING_VERSION {
+            return Err(SimulationError::Recording(format!(
+                "This is a version {} recording, this program reads version {}",
+                recording.version,
+                RECORDING_VERSION,
+            )));
+        }
+
+        return Ok(recording);
+    }
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Replay {
+        return Replay {
+            recording,
+            next_input: 0,
+            first_divergence: None,
+        };
+    }
+
+    // inputs that arrived before the next tick
+    pub fn inputs_up_to(&mut self, tick: usize) -> Vec<RecordedInput> {
+        let mut inputs: Vec<RecordedInput> = Vec::new();
+
+        while self.next_input < self.recording.inputs.len() && self.recording.inputs[self.next_input].tick <= tick {
+            inputs.push(self.recording.inputs[self.next_input].clone());
+            self.next_input += 1;
+        }
+
+        return inputs;
+    }
+
+    pub fn check(&mut self, tick: usize, checksum: u64) {
+        if self.first_divergence.is_some() || tick == 0 || tick > self.recording.checksums.len() {
+            return;
+        }
+
+        let recorded: u64 = self.recording.checksums[tick - 1];
+
+        if recorded != checksum {
+            println!("Replay diverged at tick {}: recorded checksum {:016x}, replayed {:016x}", tick, recorded, checksum);
+            self.first_divergence = Some((tick, recorded, checksum));
+        }
+    }
+
+    pub fn is_finished(&self, tick: usize) -> bool {
+        return tick >= self.recording.checksums.len();
+    }
+
+    // None when every tick matched
+    pub fn first_divergent_tick(&self) -> Option<usize> {
+        return self.first_divergence.map(|(tick, _, _)| tick);
+    }
+
+    pub fn summary(&self) -> String {
+        return match self.first_divergence {
+            Some((tick, _, _)) => format!(
+                "Replay of {} ticks diverged from the recording at tick {}",
+                self.recording.checksums.len(),
+                tick,
+            ),
+            None => format!("Replay matched the recording over all {} ticks", self.recording.checksums.len()),
+        };
+    }
+}
+
+// fltk's MouseButton has no serde support, it is stored as the button number
+pub mod mouse_button {
+    use fltk::app::MouseButton;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(button: &MouseButton, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_u8(*button as u8);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MouseButton, D::Error> {
+        return match u8::deserialize(deserializer)? {
+            1 => Ok(MouseButton::Left),
+            2 => Ok(MouseButton::Middle),
+            3 => Ok(MouseButton::Right),
+            other => Err(serde::de::Error::custom(format!("unknown mouse button {}", other))),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{Message, Question, Session, UserInterface};
+
+    struct SilentInterface;
+
+    impl UserInterface for SilentInterface {
+        fn answer(&mut self, _question: &Question) -> Option<String> {
+            return None;
+        }
+
+        fn alert(&mut self, _message: &str) {}
+
+        fn inform(&mut self, _message: &str) {}
+
+        fn set_title(&mut self, _title: &str) {}
+
+        fn quit(&mut self) {}
+    }
+
+    // the random walk draws from the seeded generator every step
+    fn seeded_state(seed: u64) -> State {
+        let mut state: State = State::new_with_seed(240, 200, seed);
+        state.random_walk_step = 2.0;
+        state.sticky_collisions = true;
+
+        for i in 0..8 {
+            state.add_circle_at(25.0 + 25.0 * i as f64, 40.0 + 30.0 * (i % 3) as f64, 1.5, -1.0, Some((8.0, 1.0))).unwrap();
+        }
+
+        return state;
+    }
+
+    fn checksums(mut state: State, steps: usize) -> Vec<u64> {
+        let mut checksums: Vec<u64> = Vec::new();
+
+        for _ in 0..steps {
+            state.progress_one_step();
+            checksums.push(state.checksum());
+        }
+
+        return checksums;
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_checksums() {
+        let first: Vec<u64> = checksums(seeded_state(11), 300);
+
+        assert_eq!(first, checksums(seeded_state(11), 300));
+        assert_ne!(first, checksums(seeded_state(12), 300));
+    }
+
+    #[test]
+    fn a_saved_recording_replays_to_the_same_checksum() {
+        let mut interface: SilentInterface = SilentInterface;
+        let mut session: Session = Session::new(seeded_state(5));
+
+        session.handle(Message::StartRecording, &mut interface);
+
+        for tick in 0..200 {
+            match tick {
+                40 => session.handle(Message::GravityToggled, &mut interface),
+                90 => session.handle(Message::AddSeedCircle, &mut interface),
+                150 => session.handle(Message::RandomWalkToggled, &mut interface),
+                _ => {}
+            }
+
+            session.handle(Message::Tick, &mut interface);
+        }
+
+        let recording: Recording = session.recording.take().unwrap();
+        let final_checksum: u64 = *recording.checksums.last().unwrap();
+        let path: String = std::env::temp_dir().join(format!("recording-{}.ron", std::process::id())).display().to_string();
+
+        recording.save(&path).unwrap();
+
+        // a different seed, the recording brings its own
+        let mut replaying: Session = Session::new(State::new_with_seed(240, 200, 99));
+        let started: Result<(), SimulationError> = replaying.start_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+        started.unwrap();
+
+        while replaying.replay.is_some() {
+            replaying.handle(Message::Tick, &mut interface);
+        }
+
+        assert_eq!(replaying.finished_replay.as_ref().and_then(|replay| replay.first_divergent_tick()), None);
+        assert_eq!(replaying.world_state.checksum(), final_checksum);
+    }
+}
@@ -0,0 +1,137 @@
+// Time reversal test: run forward, negate every velocity, run the same number of steps and see how close
+// the circles get back to where they started. Random walk, thermostats, drag and SPH are not reversible,
+// so they should be switched off for the test to be meaningful. Neither is the damping of circles faster than
+// 10 pixels a step, which stays on: a circle flung by dragging it with the mouse spoils the test until it has
+// slowed down.
+
+use std::collections::HashMap;
+
+use crate::state::State;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Forward,
+    Backward,
+}
+
+pub struct ReversibilityTest {
+    steps: usize,
+    start_time: usize,
+    phase: Phase,
+    start_positions: HashMap<usize, (f64, f64)>, // by circle id
+}
+
+pub struct ReversibilityReport {
+    pub steps: usize,
+    pub errors: Vec<(usize, f64)>, // circle id and distance from the starting position
+    pub max_error: f64,
+    pub mean_error: f64,
+}
+
+impl ReversibilityTest {
+    pub fn start(state: &State, steps: usize) -> ReversibilityTest {
+        let mut start_positions: HashMap<usize, (f64, f64)> = HashMap::with_capacity(state.circles.len());
+
+        for circle in state.circles.iter() {
+            start_positions.insert(circle.id, (circle.x_pos, circle.y_pos));
+        }
+
+        return ReversibilityTest {
+            steps: usize::max(steps, 1),
+            start_time: state.global_time,
+            phase: Phase::Forward,
+            start_positions,
+        };
+    }
+
+    // call after every step; reverses time half way and returns the report once the circles are back
+    pub fn after_step(&mut self, state: &mut State) -> Option<ReversibilityReport> {
+        let elapsed: usize = state.global_time.saturating_sub(self.start_time);
+
+        if self.phase == Phase::Forward && elapsed >= self.steps {
+            state.reverse_time();
+            self.phase = Phase::Backward;
+        } else if self.phase == Phase::Backward && elapsed >= 2 * self.steps {
+            // restore the original direction of motion
+            state.reverse_time();
+
+            return Some(self.make_report(state));
+        }
+
+        return None;
+    }
+
+    fn make_report(&self, state: &State) -> ReversibilityReport {
+        let mut errors: Vec<(usize, f64)> = Vec::new();
+
+        for circle in state.circles.iter() {
+            if let Some(&(x, y)) = self.start_positions.get(&circle.id) {
+                let error: f64 = f64::sqrt((circle.x_pos - x) * (circle.x_pos - x) + (circle.y_pos - y) * (circle.y_pos - y));
+
+                errors.push((circle.id, error));
+            }
+        }
+
+        errors.sort_by_key(|&(id, _)| id);
+
+        let max_error: f64 = errors.iter().map(|&(_, error)| error).fold(0.0, f64::max);
+        let mean_error: f64 = if errors.is_empty() {
+            0.0
+        } else {
+            errors.iter().map(|&(_, error)| error).sum::<f64>() / errors.len() as f64
+        };
+
+        return ReversibilityReport {
+            steps: self.steps,
+            errors,
+            max_error,
+            mean_error,
+        };
+    }
+}
+
+impl ReversibilityReport {
+    pub fn summary(&self) -> String {
+        return format!(
+            "After {} steps forward and {} back:\nmaximum positional error {:.6}\nmean positional error {:.6}\nover {} circles",
+            self.steps,
+            self.steps,
+            self.max_error,
+            self.mean_error,
+            self.errors.len(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_until_report(state: &mut State, steps: usize) -> ReversibilityReport {
+        let mut test: ReversibilityTest = ReversibilityTest::start(state, steps);
+
+        loop {
+            state.progress_one_step();
+
+            if let Some(report) = test.after_step(state) {
+                return report;
+            }
+        }
+    }
+
+    #[test]
+    fn reversing_brings_the_circles_back() {
+        let mut state: State = State::new_with_seed(200, 160, 1);
+
+        // slow enough never to be damped, fast enough to hit the walls and each other
+        state.add_circle_at(40.0, 40.0, 3.0, 1.0, Some((10.0, 1.0))).unwrap();
+        state.add_circle_at(150.0, 50.0, -2.0, 2.5, Some((14.0, 2.0))).unwrap();
+        state.add_circle_at(90.0, 120.0, 1.0, -3.0, Some((8.0, 0.5))).unwrap();
+
+        let report: ReversibilityReport = run_until_report(&mut state, 100);
+
+        assert_eq!(state.global_time, 200);
+        assert_eq!(report.errors.len(), 3);
+        assert!(report.max_error < 1e-6, "{}", report.summary());
+    }
+}
@@ -0,0 +1,107 @@
+// Scenario files: a whole world with its settings, written as RON so that it can be read and edited by hand.
+// The version is bumped whenever the layout changes; older files are rejected rather than misread.
+
+use std::fs;
+
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::BackgroundImage,
+    circle::{Circle, CircleLabel},
+    cluster::Cluster,
+    common_structs::RGBColor,
+    error::SimulationError,
+    flow_field::FlowField,
+    fluid::FluidRegion,
+    heatmap::HeatmapSettings,
+    sensor::SensorShape,
+    sph::SphParameters,
+    thermostat::Thermostat,
+    trails::TrailSettings,
+};
+
+pub const SCENARIO_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SavedBackground {
+    Uniform(RGBColor),
+    Pixels(Vec<u8>), // rgb values row by row, for backgrounds that are not a single colour
+    Image(BackgroundImage), // read again from the file when the scenario is opened
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedSensor {
+    pub name: String,
+    pub shape: SensorShape,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub sticky_collisions: bool,
+    pub random_walk_step: f64,
+    pub gravity: f64,
+    pub fluid_regions: Vec<FluidRegion>,
+    pub sph: SphParameters,
+    pub render_fluid_as_surface: bool,
+    pub flow_field: Option<FlowField>,
+    pub flow_drag: f64,
+    pub show_flow_field: bool,
+    #[serde(default)]
+    pub circle_labels: CircleLabel,
+    #[serde(default)]
+    pub trails: TrailSettings,
+    #[serde(default)]
+    pub heatmap: HeatmapSettings,
+    pub thermostat: Thermostat,
+    pub target_temperature: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Scenario {
+    pub version: u32,
+    pub width: f64,
+    pub height: f64,
+    pub background: SavedBackground,
+    pub settings: Settings,
+    pub seed: u64,
+    pub rng_word_position: u64, // how far the seeded generator has advanced, so that a loaded run continues identically
+    pub global_time: usize,
+    pub next_circle_id: usize,
+    pub next_cluster_id: usize,
+    pub circles: Vec<Circle>,
+    pub clusters: Vec<Cluster>,
+    pub sensors: Vec<SavedSensor>, // counters are not saved
+}
+
+// read first, so that a file from another version gets a clear message instead of a parse error
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u32,
+}
+
+impl Scenario {
+    pub fn save(&self, path: &str) -> Result<(), SimulationError> {
+        let text: String = ron::ser::to_string_pretty(self, PrettyConfig::default())?;
+        fs::write(path, text)?;
+
+        return Ok(());
+    }
+
+    pub fn load(path: &str) -> Result<Scenario, SimulationError> {
+        let text: String = fs::read_to_string(path)?;
+
+        let header: VersionHeader = ron::from_str(&text)
+            .map_err(|error| SimulationError::Scenario(format!("Not a scenario file: {}", error)))?;
+
+        if header.version != SCENARIO_VERSION {
+            return Err(SimulationError::Scenario(format!(
+                "This is a version {} scenario, this program reads version {}",
+                header.version,
+                SCENARIO_VERSION,
+            )));
+        }
+
+        return Ok(ron::from_str(&text)?);
+    }
+}
@@ -0,0 +1,481 @@
+// Rhai scripts driving a scenario. The top level of the script runs once when it is loaded (scenario setup),
+// afterwards the optional hooks are called:
+//     fn on_step() {}              after every live step
+//     fn on_collision(event) {}    for every collision of that step, before on_step
+//     fn on_click(x, y) {}         left click on the image, replaces adding a circle
+//
+// Functions available to scripts (numbers may be written as integers or floats):
+//     time(), world_width(), world_height(), temperature(), momentum(), circle_count()
+//     circles() -> array of circle maps, circle(id) -> circle map or (), circle_at(x, y) -> id or -1
+//     add_circle(x, y, x_vel, y_vel), add_circle(x, y, x_vel, y_vel, radius, mass), remove_circle(id)
+//     set_velocity(id, x_vel, y_vel), set_position(id, x, y)
+//     apply_force(id, fx, fy)      changes the velocity by f / mass once, call it from on_step for a lasting force
+//     set_opacity(id, opacity), set_blend_mode(id, "normal" | "additive" | "multiply"), set_name(id, name)
+//     set_trail(id, bool), set_trails(bool, length)
+//     set_gravity(g), set_wind(x_vel, y_vel), wind_off(), set_random_walk(step),
+//     set_sticky_collisions(bool), set_target_temperature(t)
+//
+// Queries see the world as it was when the hook was called, changes are applied once the hook returns.
+// Radius and mass must be positive numbers. A run that takes more than MAX_OPERATIONS operations (e.g. an
+// endless loop) is stopped with an error instead of freezing the window.
+
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use crate::{common_structs::BlendMode, error::SimulationError, events::CollisionEvent, flow_field::FlowField, state::State};
+
+const MAX_OPERATIONS: u64 = 5_000_000; // for the top level and for every hook call
+
+enum ScriptCommand {
+    AddCircle {
+        x: f64,
+        y: f64,
+        x_vel: f64,
+        y_vel: f64,
+        size: Option<(f64, f64)>, // radius and mass, random as for clicked circles when not given
+    },
+    RemoveCircle(usize),
+    SetVelocity {
+        id: usize,
+        x_vel: f64,
+        y_vel: f64,
+    },
+    SetPosition {
+        id: usize,
+        x: f64,
+        y: f64,
+    },
+    ApplyForce {
+        id: usize,
+        force_x: f64,
+        force_y: f64,
+    },
+    SetOpacity {
+        id: usize,
+        opacity: f64,
+    },
+    SetBlendMode {
+        id: usize,
+        mode: BlendMode,
+    },
+    SetName {
+        id: usize,
+        name: String,
+    },
+    SetTrail {
+        id: usize,
+        leaves_trail: bool,
+    },
+    SetTrails {
+        enabled: bool,
+        length: usize,
+    },
+    SetGravity(f64),
+    SetWind(Option<FlowField>),
+    SetRandomWalk(f64),
+    SetStickyCollisions(bool),
+    SetTargetTemperature(f64),
+}
+
+// copy of the parts of State that scripts can read, refreshed before every hook
+#[derive(Default)]
+struct WorldView {
+    global_time: usize,
+    width: f64,
+    height: f64,
+    temperature: f64,
+    momentum: f64,
+    circles: Vec<Map>,
+}
+
+pub struct Script {
+    pub path: String,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    commands: Rc<RefCell<Vec<ScriptCommand>>>,
+    view: Rc<RefCell<WorldView>>,
+}
+
+// scripts may write 3 as well as 3.0
+fn to_float(value: &Dynamic) -> f64 {
+    if let Ok(float) = value.as_float() {
+        return float;
+    }
+
+    return value.as_int().map(|int| int as f64).unwrap_or(0.0);
+}
+
+// radius and mass end up as divisors in the collision response, so anything but a positive number is refused
+fn to_size(radius: &Dynamic, mass: &Dynamic) -> Result<(f64, f64), Box<EvalAltResult>> {
+    let (radius, mass) = (to_float(radius), to_float(mass));
+
+    if !radius.is_finite() || radius <= 0.0 || !mass.is_finite() || mass <= 0.0 {
+        return Err(format!("Radius and mass should be positive numbers, not {} and {}", radius, mass).into());
+    }
+
+    return Ok((radius, mass));
+}
+
+fn to_id(value: &Dynamic) -> usize {
+    return usize::try_from(value.as_int().unwrap_or(-1)).unwrap_or(usize::MAX);
+}
+
+impl Script {
+    // compiles the file and runs its top level against the state
+    pub fn load(path: &str, state: &mut State) -> Result<Script, SimulationError> {
+        let commands: Rc<RefCell<Vec<ScriptCommand>>> = Rc::new(RefCell::new(Vec::new()));
+        let view: Rc<RefCell<WorldView>> = Rc::new(RefCell::new(WorldView::default()));
+
+        let mut engine: Engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        Script::register_queries(&mut engine, &view);
+        Script::register_commands(&mut engine, &commands);
+
+        let ast: AST = engine
+            .compile_file(PathBuf::from(path))
+            .map_err(|error| SimulationError::Script(error.to_string()))?;
+
+        let mut script: Script = Script {
+            path: String::from(path),
+            engine,
+            ast,
+            scope: Scope::new(),
+            commands,
+            view,
+        };
+
+        script.refresh_view(state);
+        script
+            .engine
+            .run_ast_with_scope(&mut script.scope, &script.ast)
+            .map_err(|error| SimulationError::Script(error.to_string()))?;
+        script.apply_commands(state);
+
+        return Ok(script);
+    }
+
+    pub fn has_hook(&self, name: &str) -> bool {
+        return self.ast.iter_functions().any(|function| function.name == name);
+    }
+
+    // call after every live step with the collisions of that step
+    pub fn after_step(&mut self, state: &mut State, collisions: &Vec<CollisionEvent>) -> Result<(), SimulationError> {
+        if self.has_hook("on_collision") {
+            for event in collisions.iter() {
+                self.call_hook(state, "on_collision", (Dynamic::from_map(Script::collision_to_map(event)),))?;
+            }
+        }
+
+        if self.has_hook("on_step") {
+            self.call_hook(state, "on_step", ())?;
+        }
+
+        return Ok(());
+    }
+
+    // returns false when the script has no on_click hook, so the click should be handled as usual
+    pub fn click(&mut self, state: &mut State, x: f64, y: f64) -> Result<bool, SimulationError> {
+        if !self.has_hook("on_click") {
+            return Ok(false);
+        }
+
+        self.call_hook(state, "on_click", (x, y))?;
+
+        return Ok(true);
+    }
+
+    fn call_hook(&mut self, state: &mut State, name: &str, arguments: impl rhai::FuncArgs) -> Result<(), SimulationError> {
+        self.refresh_view(state);
+
+        let result: Result<Dynamic, SimulationError> = self
+            .engine
+            // the top level is the scenario setup and only ran once, at load time
+            .call_fn_with_options::<Dynamic>(CallFnOptions::new().eval_ast(false), &mut self.scope, &self.ast, name, arguments)
+            .map_err(|error| SimulationError::Script(format!("Error in {} of script {}: {}", name, self.path, error)));
+
+        // whatever ran before an error still takes effect
+        self.apply_commands(state);
+
+        return result.map(|_| ());
+    }
+
+    fn refresh_view(&self, state: &State) {
+        let mut view = self.view.borrow_mut();
+
+        view.global_time = state.global_time;
+        view.width = state.width;
+        view.height = state.height;
+        view.temperature = state.get_temperature();
+        view.momentum = state.get_total_momentum();
+        view.circles = state.circles.iter().map(|circle| {
+            let mut map: Map = Map::new();
+
+            map.insert("id".into(), Dynamic::from_int(circle.id as i64));
+            map.insert("name".into(), Dynamic::from(circle.name.clone()));
+            map.insert("x".into(), Dynamic::from_float(circle.x_pos));
+            map.insert("y".into(), Dynamic::from_float(circle.y_pos));
+            map.insert("x_vel".into(), Dynamic::from_float(circle.x_vel));
+            map.insert("y_vel".into(), Dynamic::from_float(circle.y_vel));
+            map.insert("radius".into(), Dynamic::from_float(circle.radius));
+            map.insert("mass".into(), Dynamic::from_float(circle.mass));
+            map.insert("is_fluid".into(), Dynamic::from_bool(circle.is_fluid));
+            map.insert("opacity".into(), Dynamic::from_float(circle.opacity));
+            map.insert("cluster".into(), match circle.cluster_id {
+                Some(cluster_id) => Dynamic::from_int(cluster_id as i64),
+                None => Dynamic::UNIT,
+            });
+
+            map
+        }).collect();
+    }
+
+    fn collision_to_map(event: &CollisionEvent) -> Map {
+        let mut map: Map = Map::new();
+
+        map.insert("time".into(), Dynamic::from_int(event.global_time as i64));
+        map.insert("a".into(), Dynamic::from_int(event.circle_a as i64));
+        map.insert("b".into(), Dynamic::from_int(event.circle_b as i64));
+        map.insert("x".into(), Dynamic::from_float(event.contact_x));
+        map.insert("y".into(), Dynamic::from_float(event.contact_y));
+        map.insert("normal_x".into(), Dynamic::from_float(event.normal_x));
+        map.insert("normal_y".into(), Dynamic::from_float(event.normal_y));
+        map.insert("speed".into(), Dynamic::from_float(event.relative_speed));
+        map.insert("impulse".into(), Dynamic::from_float(event.impulse));
+        map.insert("sticky".into(), Dynamic::from_bool(event.is_sticky));
+
+        return map;
+    }
+
+    fn register_queries(engine: &mut Engine, view: &Rc<RefCell<WorldView>>) {
+        let v = view.clone();
+        engine.register_fn("time", move || v.borrow().global_time as i64);
+        let v = view.clone();
+        engine.register_fn("world_width", move || v.borrow().width);
+        let v = view.clone();
+        engine.register_fn("world_height", move || v.borrow().height);
+        let v = view.clone();
+        engine.register_fn("temperature", move || v.borrow().temperature);
+        let v = view.clone();
+        engine.register_fn("momentum", move || v.borrow().momentum);
+        let v = view.clone();
+        engine.register_fn("circle_count", move || v.borrow().circles.len() as i64);
+
+        let v = view.clone();
+        engine.register_fn("circles", move || -> Array {
+            v.borrow().circles.iter().map(|map| Dynamic::from_map(map.clone())).collect()
+        });
+
+        let v = view.clone();
+        engine.register_fn("circle", move |id: Dynamic| -> Dynamic {
+            let id: i64 = id.as_int().unwrap_or(-1);
+
+            for map in v.borrow().circles.iter() {
+                if map["id"].as_int() == Ok(id) {
+                    return Dynamic::from_map(map.clone());
+                }
+            }
+
+            return Dynamic::UNIT;
+        });
+
+        let v = view.clone();
+        engine.register_fn("circle_at", move |x: Dynamic, y: Dynamic| -> i64 {
+            let x: f64 = to_float(&x);
+            let y: f64 = to_float(&y);
+
+            // topmost circle first, as for selection
+            for map in v.borrow().circles.iter().rev() {
+                let dx: f64 = to_float(&map["x"]) - x;
+                let dy: f64 = to_float(&map["y"]) - y;
+                let radius: f64 = to_float(&map["radius"]);
+
+                if dx * dx + dy * dy < radius * radius {
+                    return map["id"].as_int().unwrap_or(-1);
+                }
+            }
+
+            return -1;
+        });
+    }
+
+    fn register_commands(engine: &mut Engine, commands: &Rc<RefCell<Vec<ScriptCommand>>>) {
+        let c = commands.clone();
+        engine.register_fn("add_circle", move |x: Dynamic, y: Dynamic, x_vel: Dynamic, y_vel: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::AddCircle {
+                x: to_float(&x),
+                y: to_float(&y),
+                x_vel: to_float(&x_vel),
+                y_vel: to_float(&y_vel),
+                size: None,
+            });
+        });
+
+        let c = commands.clone();
+        engine.register_fn(
+            "add_circle",
+            move |x: Dynamic, y: Dynamic, x_vel: Dynamic, y_vel: Dynamic, radius: Dynamic, mass: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                c.borrow_mut().push(ScriptCommand::AddCircle {
+                    x: to_float(&x),
+                    y: to_float(&y),
+                    x_vel: to_float(&x_vel),
+                    y_vel: to_float(&y_vel),
+                    size: Some(to_size(&radius, &mass)?),
+                });
+
+                return Ok(());
+            },
+        );
+
+        let c = commands.clone();
+        engine.register_fn("remove_circle", move |id: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::RemoveCircle(to_id(&id)));
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_velocity", move |id: Dynamic, x_vel: Dynamic, y_vel: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetVelocity { id: to_id(&id), x_vel: to_float(&x_vel), y_vel: to_float(&y_vel) });
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_position", move |id: Dynamic, x: Dynamic, y: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetPosition { id: to_id(&id), x: to_float(&x), y: to_float(&y) });
+        });
+
+        let c = commands.clone();
+        engine.register_fn("apply_force", move |id: Dynamic, force_x: Dynamic, force_y: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let (force_x, force_y) = (to_float(&force_x), to_float(&force_y));
+
+            if !force_x.is_finite() || !force_y.is_finite() {
+                return Err(format!("Force ({}, {}) is not a number", force_x, force_y).into());
+            }
+
+            c.borrow_mut().push(ScriptCommand::ApplyForce { id: to_id(&id), force_x, force_y });
+
+            return Ok(());
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_opacity", move |id: Dynamic, opacity: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetOpacity { id: to_id(&id), opacity: to_float(&opacity).clamp(0.0, 1.0) });
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_blend_mode", move |id: Dynamic, name: &str| -> Result<(), Box<EvalAltResult>> {
+            let mode: BlendMode = match name {
+                "normal" => BlendMode::Normal,
+                "additive" => BlendMode::Additive,
+                "multiply" => BlendMode::Multiply,
+                _ => return Err(format!("Unknown blend mode \"{}\"", name).into()),
+            };
+
+            c.borrow_mut().push(ScriptCommand::SetBlendMode { id: to_id(&id), mode });
+
+            return Ok(());
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_name", move |id: Dynamic, name: &str| {
+            c.borrow_mut().push(ScriptCommand::SetName { id: to_id(&id), name: String::from(name) });
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_trail", move |id: Dynamic, leaves_trail: bool| {
+            c.borrow_mut().push(ScriptCommand::SetTrail { id: to_id(&id), leaves_trail });
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_trails", move |enabled: bool, length: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetTrails { enabled, length: to_float(&length).max(2.0) as usize });
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_gravity", move |gravity: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetGravity(to_float(&gravity)));
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_wind", move |x_vel: Dynamic, y_vel: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetWind(Some(FlowField::Uniform { x_vel: to_float(&x_vel), y_vel: to_float(&y_vel) })));
+        });
+
+        let c = commands.clone();
+        engine.register_fn("wind_off", move || {
+            c.borrow_mut().push(ScriptCommand::SetWind(None));
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_random_walk", move |step: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetRandomWalk(to_float(&step)));
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_sticky_collisions", move |sticky: bool| {
+            c.borrow_mut().push(ScriptCommand::SetStickyCollisions(sticky));
+        });
+
+        let c = commands.clone();
+        engine.register_fn("set_target_temperature", move |temperature: Dynamic| {
+            c.borrow_mut().push(ScriptCommand::SetTargetTemperature(to_float(&temperature)));
+        });
+    }
+
+    fn apply_commands(&mut self, state: &mut State) {
+        let commands: Vec<ScriptCommand> = std::mem::take(&mut *self.commands.borrow_mut());
+
+        for command in commands {
+            match command {
+                ScriptCommand::AddCircle { x, y, x_vel, y_vel, size } => {
+                    if let Err(error) = state.add_circle_at(x, y, x_vel, y_vel, size) {
+                        println!("{}", error);
+                    }
+                }
+                ScriptCommand::RemoveCircle(id) => state.remove_circle_by_id(id),
+                // clustered circles move with their cluster and are left alone
+                ScriptCommand::SetVelocity { id, x_vel, y_vel } => {
+                    if let Some(circle) = state.circles.iter_mut().find(|circle| circle.id == id && circle.cluster_id.is_none()) {
+                        circle.x_vel = x_vel;
+                        circle.y_vel = y_vel;
+                    }
+                }
+                ScriptCommand::SetPosition { id, x, y } => {
+                    if let Some(circle) = state.circles.iter_mut().find(|circle| circle.id == id && circle.cluster_id.is_none()) {
+                        circle.x_pos = x;
+                        circle.y_pos = y;
+                    }
+                }
+                ScriptCommand::ApplyForce { id, force_x, force_y } => state.apply_force(id, force_x, force_y),
+                ScriptCommand::SetOpacity { id, opacity } => {
+                    if let Some(circle) = state.circles.iter_mut().find(|circle| circle.id == id) {
+                        circle.opacity = opacity;
+                    }
+                }
+                ScriptCommand::SetBlendMode { id, mode } => {
+                    if let Some(circle) = state.circles.iter_mut().find(|circle| circle.id == id) {
+                        circle.blend_mode = mode;
+                    }
+                }
+                ScriptCommand::SetName { id, name } => {
+                    if let Err(error) = state.rename_circle(id, &name) {
+                        println!("{}", error);
+                    }
+                }
+                ScriptCommand::SetTrail { id, leaves_trail } => {
+                    if let Some(circle) = state.circles.iter_mut().find(|circle| circle.id == id) {
+                        circle.leaves_trail = leaves_trail;
+                    }
+                }
+                ScriptCommand::SetTrails { enabled, length } => {
+                    state.trail_settings.enabled = enabled;
+                    state.trail_settings.length = length;
+                }
+                ScriptCommand::SetGravity(gravity) => state.gravity = gravity,
+                ScriptCommand::SetWind(flow_field) => state.flow_field = flow_field,
+                ScriptCommand::SetRandomWalk(step) => state.random_walk_step = step,
+                ScriptCommand::SetStickyCollisions(sticky) => state.sticky_collisions = sticky,
+                ScriptCommand::SetTargetTemperature(temperature) => state.target_temperature = temperature,
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+// Sensors: rectangles, circles and lines placed in the world that count circles passing them
+// without touching their motion. A circle is inside an area sensor when its centre is.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circle::Circle,
+    common_structs::{RGBCanvas, RGBColor},
+    drawing::{Canvas, LineStyle},
+    events::{SensorEvent, SensorEventKind},
+};
+
+const DIRECTION_TICK_LENGTH: f64 = 6.0;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SensorKind {
+    Rectangle,
+    Circle,
+    Line,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SensorShape {
+    Rectangle {
+        x_start: f64,
+        x_end: f64,
+        y_start: f64,
+        y_end: f64,
+    },
+    Circle {
+        x_pos: f64,
+        y_pos: f64,
+        radius: f64,
+    },
+    // crossings are forward along the normal (-dy, dx) of the direction from start to end
+    Line {
+        x_start: f64,
+        y_start: f64,
+        x_end: f64,
+        y_end: f64,
+    },
+}
+
+pub struct Sensor {
+    pub id: usize,
+    pub name: String,
+    pub shape: SensorShape,
+    pub color: RGBColor,
+    pub enter_count: usize,
+    pub exit_count: usize,
+    pub forward_crossings: usize,
+    pub backward_crossings: usize,
+    inside: HashSet<usize>, // ids of circles currently inside an area sensor
+    last_positions: HashMap<usize, (f64, f64)>, // circle centres at the previous update, by id
+}
+
+impl SensorShape {
+    // rectangles and circles are given by two corners or by centre and a point on the rim,
+    // None when the shape would be too small to ever be hit
+    pub fn from_drag(kind: SensorKind, x_start: f64, y_start: f64, x_end: f64, y_end: f64) -> Option<SensorShape> {
+        let length: f64 = f64::sqrt((x_end - x_start) * (x_end - x_start) + (y_end - y_start) * (y_end - y_start));
+
+        if length < 2.0 {
+            return None;
+        }
+
+        let shape: SensorShape = match kind {
+            SensorKind::Rectangle => SensorShape::Rectangle {
+                x_start: f64::min(x_start, x_end),
+                x_end: f64::max(x_start, x_end),
+                y_start: f64::min(y_start, y_end),
+                y_end: f64::max(y_start, y_end),
+            },
+            SensorKind::Circle => SensorShape::Circle {
+                x_pos: x_start,
+                y_pos: y_start,
+                radius: length,
+            },
+            SensorKind::Line => SensorShape::Line { x_start, y_start, x_end, y_end },
+        };
+
+        return Some(shape);
+    }
+
+    pub fn scaled(&self, factor: f64) -> SensorShape {
+        return match *self {
+            SensorShape::Rectangle { x_start, x_end, y_start, y_end } => SensorShape::Rectangle {
+                x_start: x_start * factor,
+                x_end: x_end * factor,
+                y_start: y_start * factor,
+                y_end: y_end * factor,
+            },
+            SensorShape::Circle { x_pos, y_pos, radius } => SensorShape::Circle {
+                x_pos: x_pos * factor,
+                y_pos: y_pos * factor,
+                radius: radius * factor,
+            },
+            SensorShape::Line { x_start, y_start, x_end, y_end } => SensorShape::Line {
+                x_start: x_start * factor,
+                y_start: y_start * factor,
+                x_end: x_end * factor,
+                y_end: y_end * factor,
+            },
+        };
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        match *self {
+            SensorShape::Rectangle { x_start, x_end, y_start, y_end } => {
+                return x >= x_start && x <= x_end && y >= y_start && y <= y_end;
+            }
+            SensorShape::Circle { x_pos, y_pos, radius } => {
+                return (x - x_pos) * (x - x_pos) + (y - y_pos) * (y - y_pos) <= radius * radius;
+            }
+            SensorShape::Line { .. } => return false,
+        }
+    }
+
+    // Some(forward) when the path from (x_from, y_from) to (x_to, y_to) crosses a line sensor
+    fn crossing(&self, x_from: f64, y_from: f64, x_to: f64, y_to: f64) -> Option<bool> {
+        if let SensorShape::Line { x_start, y_start, x_end, y_end } = *self {
+            let dir_x: f64 = x_end - x_start;
+            let dir_y: f64 = y_end - y_start;
+            let move_x: f64 = x_to - x_from;
+            let move_y: f64 = y_to - y_from;
+
+            let denominator: f64 = dir_x * move_y - dir_y * move_x;
+
+            if denominator == 0.0 {
+                return None;
+            }
+
+            // parameters along the sensor line (s) and along the path of the circle (t)
+            let s: f64 = ((x_from - x_start) * move_y - (y_from - y_start) * move_x) / denominator;
+            let t: f64 = ((x_from - x_start) * dir_y - (y_from - y_start) * dir_x) / denominator;
+
+            if s >= 0.0 && s <= 1.0 && t > 0.0 && t <= 1.0 {
+                return Some(move_x * -dir_y + move_y * dir_x > 0.0);
+            }
+        }
+
+        return None;
+    }
+
+    fn is_line(&self) -> bool {
+        return matches!(self, SensorShape::Line { .. });
+    }
+}
+
+impl Sensor {
+    pub fn new(id: usize, shape: SensorShape, color: RGBColor) -> Sensor {
+        let name: String = match shape {
+            SensorShape::Rectangle { .. } => format!("Rectangle {}", id),
+            SensorShape::Circle { .. } => format!("Circle {}", id),
+            SensorShape::Line { .. } => format!("Line {}", id),
+        };
+
+        return Sensor {
+            id,
+            name,
+            shape,
+            color,
+            enter_count: 0,
+            exit_count: 0,
+            forward_crossings: 0,
+            backward_crossings: 0,
+            inside: HashSet::new(),
+            last_positions: HashMap::new(),
+        };
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.enter_count = 0;
+        self.exit_count = 0;
+        self.forward_crossings = 0;
+        self.backward_crossings = 0;
+    }
+
+    // remembers where the circles are without producing events,
+    // used when the sensor is placed and whenever the circles jump (e.g. history scrubbing)
+    pub fn track(&mut self, circles: &Vec<Circle>) {
+        self.inside.clear();
+        self.last_positions.clear();
+
+        for circle in circles.iter() {
+            self.last_positions.insert(circle.id, (circle.x_pos, circle.y_pos));
+
+            if self.shape.contains(circle.x_pos, circle.y_pos) {
+                self.inside.insert(circle.id);
+            }
+        }
+    }
+
+    pub fn update(&mut self, circles: &Vec<Circle>, global_time: usize) -> Vec<SensorEvent> {
+        let mut events: Vec<SensorEvent> = Vec::new();
+        let mut positions: HashMap<usize, (f64, f64)> = HashMap::with_capacity(circles.len());
+        let mut inside: HashSet<usize> = HashSet::new();
+
+        for circle in circles.iter() {
+            positions.insert(circle.id, (circle.x_pos, circle.y_pos));
+
+            let is_inside: bool = self.shape.contains(circle.x_pos, circle.y_pos);
+
+            if is_inside {
+                inside.insert(circle.id);
+            }
+
+            // circles added since the last update are only tracked from now on
+            let (x_from, y_from) = match self.last_positions.get(&circle.id) {
+                Some(&position) => position,
+                None => continue,
+            };
+
+            let kind: Option<SensorEventKind> = if self.shape.is_line() {
+                self.shape
+                    .crossing(x_from, y_from, circle.x_pos, circle.y_pos)
+                    .map(|forward| SensorEventKind::Crossing { forward })
+            } else if is_inside && !self.inside.contains(&circle.id) {
+                Some(SensorEventKind::Enter)
+            } else if !is_inside && self.inside.contains(&circle.id) {
+                Some(SensorEventKind::Exit)
+            } else {
+                None
+            };
+
+            if let Some(kind) = kind {
+                match kind {
+                    SensorEventKind::Enter => self.enter_count += 1,
+                    SensorEventKind::Exit => self.exit_count += 1,
+                    SensorEventKind::Crossing { forward: true } => self.forward_crossings += 1,
+                    SensorEventKind::Crossing { forward: false } => self.backward_crossings += 1,
+                }
+
+                events.push(SensorEvent {
+                    global_time,
+                    sensor_id: self.id,
+                    circle_id: circle.id,
+                    kind,
+                });
+            }
+        }
+
+        // removed circles simply stop being tracked
+        self.inside = inside;
+        self.last_positions = positions;
+
+        return events;
+    }
+
+    // one line for the side panel
+    pub fn counter_summary(&self) -> String {
+        if self.shape.is_line() {
+            return format!(
+                "{}: {} fwd, {} back, net {}",
+                self.name,
+                self.forward_crossings,
+                self.backward_crossings,
+                self.forward_crossings as i64 - self.backward_crossings as i64,
+            );
+        }
+
+        return format!(
+            "{}: {} inside, {} in, {} out",
+            self.name,
+            self.inside.len(),
+            self.enter_count,
+            self.exit_count,
+        );
+    }
+
+    // scale is the number of canvas pixels per unit of the world
+    pub fn put_on_canvas(&self, canvas: &mut RGBCanvas, scale: f64) {
+        let style: LineStyle = LineStyle::new(self.color).thickness(scale);
+
+        match self.shape.scaled(scale) {
+            SensorShape::Rectangle { x_start, x_end, y_start, y_end } => {
+                canvas.draw_rect(x_start, y_start, x_end - x_start, y_end - y_start, &style);
+            }
+            SensorShape::Circle { x_pos, y_pos, radius } => {
+                canvas.draw_circle(x_pos, y_pos, radius, self.color, scale);
+            }
+            SensorShape::Line { x_start, y_start, x_end, y_end } => {
+                canvas.draw_line(x_start, y_start, x_end, y_end, &style);
+
+                // short tick at the middle showing the forward side
+                let length: f64 = f64::sqrt((x_end - x_start) * (x_end - x_start) + (y_end - y_start) * (y_end - y_start));
+                let middle_x: f64 = (x_start + x_end) / 2.0;
+                let middle_y: f64 = (y_start + y_end) / 2.0;
+
+                canvas.draw_line(
+                    middle_x,
+                    middle_y,
+                    middle_x - (y_end - y_start) / length * DIRECTION_TICK_LENGTH * scale,
+                    middle_y + (x_end - x_start) / length * DIRECTION_TICK_LENGTH * scale,
+                    &style,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,1100 @@
+// Everything the main loop does in response to a Message, kept apart from the widgets so that the same
+// code runs in the window, headlessly and when replaying a recorded session
+
+use std::{cell::Cell, rc::Rc};
+
+use fltk::app::MouseButton;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::{BackgroundFit, BackgroundImage},
+    circle::CircleLabel,
+    common_structs::{BlendMode, Coord, RGBCanvas, RGBColor},
+    debug_draw::{DebugLayer, DebugOverlay},
+    drawing::{Canvas, LineStyle},
+    error::SimulationError,
+    events::{CollisionEvent, CollisionObserver},
+    export,
+    flow_field::FlowField,
+    fluid::FluidRegion,
+    history::{History, HistoryMode, Playback},
+    recording::{self, RecordedInput, Recording, Replay},
+    reversal::ReversibilityTest,
+    scenario::Scenario,
+    scripting::Script,
+    sensor::{SensorKind, SensorShape},
+    state::State,
+    svg,
+    thermostat::Thermostat,
+    trails::TrailStyle,
+    video::{self, VideoOptions, VideoRecorder},
+};
+
+const GRAVITY: f64 = 0.1;
+const WATER_DENSITY: f64 = 0.6;
+const WATER_VISCOSITY: f64 = 0.1;
+const HISTORY_CAPACITY: usize = 600;
+const HISTORY_KEYFRAME_INTERVAL: usize = 60;
+const AIMING_LINE_COLOR: RGBColor = RGBColor {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Message {
+    Quit,
+    AddCircleButEv,
+    RemoveCircleButEv,
+    WBev,
+    BBev,
+    GBev,
+    LGBev,
+    MouseDown(i32, i32, #[serde(with = "recording::mouse_button")] MouseButton),
+    MouseDrag(i32, i32),
+    MouseMove(i32, i32),
+    MouseReleased(i32, i32, #[serde(with = "recording::mouse_button")] MouseButton),
+    Tick,
+    StickyCollisionsToggled,
+    RandomWalkToggled,
+    AddSeedCircle,
+    GravityToggled,
+    WaterToggled,
+    PourFluid,
+    FluidSurfaceToggled,
+    WindOff,
+    WindUniform,
+    WindVortex,
+    WindShear,
+    WindFromFile,
+    FlowFieldToggled,
+    ThermostatChanged,
+    TargetTemperatureChanged,
+    ThermaliseButEv,
+    RestartWithSeed,
+    RewindButEv,
+    StepBackButEv,
+    ResumeButEv,
+    StepForwardButEv,
+    TimelineMoved,
+    HistoryCapacity,
+    HistoryDeltaToggled,
+    ReverseTime,
+    TestReversibility,
+    LogCollisionsToggled,
+    AddSensor(SensorKind),
+    ResetSensorCounters,
+    RemoveSensors,
+    OpenScenario,
+    SaveScenario,
+    SaveScenarioAs,
+    LoadScript,
+    UnloadScript,
+    StartRecording,
+    StopRecording,
+    ReplayRecording,
+    VideoRecordingToggled,
+    ExportImage,
+    ExportSvg,
+    CircleOpacity,
+    CircleBlendMode(BlendMode),
+    RenameCircle,
+    CircleLabelsChanged(CircleLabel),
+    DebugLayerToggled(DebugLayer),
+    TrailsToggled,
+    TrailStyleChanged(TrailStyle),
+    TrailLength,
+    CircleTrailToggled,
+    HeatmapToggled,
+    ResetHeatmap,
+    HeatmapCellSize,
+    HeatmapDecay,
+    ExportHeatmapCsv,
+    BackgroundColour,
+    LoadBackgroundImage(BackgroundFit),
+}
+
+pub enum Colour {
+    Black,
+    Grey,
+    LightGrey,
+    White,
+    Rgb(RGBColor),
+}
+
+// anything the handlers need to ask the user or read from a widget; the answers are recorded with the message
+pub enum Question<'a> {
+    Text {
+        prompt: &'a str,
+        default: &'a str,
+    },
+    File {
+        title: &'a str,
+        pattern: &'a str,
+    },
+    NewFile {
+        title: &'a str,
+        pattern: &'a str,
+        default_name: &'a str,
+    },
+    ThermostatChoice,
+    TargetTemperature,
+    TimelinePosition,
+    Colour {
+        title: &'a str,
+        default: RGBColor,
+    }, // answered as #rrggbb
+}
+
+pub trait UserInterface {
+    fn answer(&mut self, question: &Question) -> Option<String>;
+    fn alert(&mut self, message: &str);
+    fn inform(&mut self, message: &str);
+    fn set_title(&mut self, title: &str);
+    fn quit(&mut self);
+}
+
+// prints every collision to the terminal while View/Log collisions is checked
+struct CollisionLogger {
+    enabled: Rc<Cell<bool>>,
+}
+
+impl CollisionObserver for CollisionLogger {
+    fn on_collision(&mut self, event: &CollisionEvent) {
+        if self.enabled.get() {
+            println!(
+                "{:>5}: {:>3} <->{:>3} at ({:>7.2}, {:>7.2}), speed {:>7.3}, impulse {:>8.3}{}",
+                event.global_time,
+                event.circle_a,
+                event.circle_b,
+                event.contact_x,
+                event.contact_y,
+                event.relative_speed,
+                event.impulse,
+                if event.is_sticky {", stuck"} else {""},
+            );
+        }
+    }
+}
+
+pub struct Session {
+    pub world_state: State,
+    pub history: History,
+    pub script: Option<Script>,
+    pub scenario_path: Option<String>,
+    pub reversibility_test: Option<ReversibilityTest>,
+    pub added_circle_id: Option<usize>, // circle waiting for the second click that aims it
+    pub new_circle_coord: Coord,
+    pub cursor_coord: Coord,
+    pub sensor_tool: Option<SensorKind>, // shape of the sensor placed by the next drag on the image
+    pub is_placing_sensor: bool,
+    pub ticks: usize, // Tick messages handled so far, the clock of recordings
+    pub recording: Option<Recording>,
+    pub replay: Option<Replay>,
+    pub finished_replay: Option<Replay>, // kept so that the outcome can be read once the replay is over
+    pub video: Option<VideoRecorder>,
+    pub debug_overlay: DebugOverlay,
+    log_collisions: Rc<Cell<bool>>,
+    answers: Vec<Option<String>>, // answers given while handling the current message
+    replayed_answers: Vec<Option<String>>, // recorded answers still to be given, in reverse order
+}
+
+impl Session {
+    pub fn new(mut world_state: State) -> Session {
+        let log_collisions: Rc<Cell<bool>> = Rc::new(Cell::new(false));
+        world_state.add_collision_observer(Box::new(CollisionLogger { enabled: log_collisions.clone() }));
+
+        return Session {
+            world_state,
+            history: History::new(
+                HISTORY_CAPACITY,
+                HistoryMode::Delta { keyframe_interval: HISTORY_KEYFRAME_INTERVAL },
+            ),
+            script: None,
+            scenario_path: None,
+            reversibility_test: None,
+            added_circle_id: None,
+            new_circle_coord: Coord::new(0.0, 0.0),
+            cursor_coord: Coord::new(0.0, 0.0),
+            sensor_tool: None,
+            is_placing_sensor: false,
+            ticks: 0,
+            recording: None,
+            replay: None,
+            finished_replay: None,
+            video: None,
+            debug_overlay: DebugOverlay::default(),
+            log_collisions,
+            answers: Vec::new(),
+            replayed_answers: Vec::new(),
+        };
+    }
+
+    pub fn handle(&mut self, message: Message, ui: &mut dyn UserInterface) {
+        if self.replay.is_some() {
+            // while replaying only the clock moves the session, everything else comes from the recording
+            match message {
+                Message::Tick => self.replay_tick(ui),
+                Message::Quit
+                | Message::VideoRecordingToggled
+                | Message::ExportImage
+                | Message::ExportSvg
+                | Message::ExportHeatmapCsv
+                | Message::DebugLayerToggled(_) => self.handle_message(message, ui),
+                _ => println!("Input is ignored while a recording is replayed."),
+            }
+
+            return;
+        }
+
+        self.answers = Vec::new();
+        self.handle_message(message.clone(), ui);
+
+        if let Some(recording) = &mut self.recording {
+            match message {
+                Message::Tick => recording.checksums.push(self.world_state.checksum()),
+                // saving does not change the simulation, replaying it would only overwrite files
+                Message::StartRecording
+                | Message::StopRecording
+                | Message::SaveScenario
+                | Message::SaveScenarioAs
+                | Message::VideoRecordingToggled
+                | Message::ExportImage
+                | Message::ExportSvg
+                | Message::ExportHeatmapCsv
+                | Message::DebugLayerToggled(_) => {}
+                _ => recording.record(self.ticks, message, std::mem::take(&mut self.answers)),
+            }
+        }
+    }
+
+    fn replay_tick(&mut self, ui: &mut dyn UserInterface) {
+        let replay: &mut Replay = match &mut self.replay {
+            Some(replay) => replay,
+            None => return,
+        };
+
+        let inputs: Vec<RecordedInput> = replay.inputs_up_to(self.ticks);
+
+        for input in inputs {
+            self.replayed_answers = input.answers;
+            self.replayed_answers.reverse();
+            self.handle_message(input.message, ui);
+        }
+
+        self.handle_message(Message::Tick, ui);
+
+        let checksum: u64 = self.world_state.checksum();
+
+        if let Some(replay) = &mut self.replay {
+            // ticks are counted after the step, the first one is tick 1
+            replay.check(self.ticks, checksum);
+
+            if replay.is_finished(self.ticks) {
+                let summary: String = replay.summary();
+
+                println!("{}", summary);
+                ui.inform(&summary);
+                self.finished_replay = self.replay.take();
+            }
+        }
+    }
+
+    // live answer from the user or the recorded one while replaying
+    fn answer(&mut self, ui: &mut dyn UserInterface, question: Question) -> Option<String> {
+        let answer: Option<String> = if self.replay.is_some() {
+            self.replayed_answers.pop().flatten()
+        } else {
+            ui.answer(&question)
+        };
+
+        self.answers.push(answer.clone());
+
+        return answer;
+    }
+
+    // forgets everything that a scenario file does not hold, so that recording and replay start alike
+    fn reset_for_recording(&mut self) {
+        self.history.clear();
+        self.history.resume();
+        self.reversibility_test = None;
+        self.added_circle_id = None;
+        self.sensor_tool = None;
+        self.is_placing_sensor = false;
+        self.ticks = 0;
+        // the selection is not part of the saved initial state, drags on it would not replay
+        self.world_state.has_selected_circle = false;
+
+        if self.script.is_some() {
+            println!("Unloading the script, scripts are not part of recordings.");
+            self.script = None;
+        }
+    }
+
+    fn handle_message(&mut self, message: Message, ui: &mut dyn UserInterface) {
+        match message {
+            Message::Quit => {
+                println!("quitting the app...");
+                self.stop_video(ui);
+                ui.quit();
+            }
+            Message::AddCircleButEv => {
+                println!("Adding circle...");
+
+                let added = self.world_state.add_random_circle_at_coords(
+                    (self.world_state.width / 2.0) as i32,
+                    (self.world_state.height / 2.0) as i32,
+                    0.0,
+                    0.0,
+                );
+
+                if let Err(error) = added {
+                    println!("{}", error);
+                }
+            }
+            Message::RemoveCircleButEv => {
+                println!("Removing circle...");
+
+                self.world_state.remove_all_circles();
+            }
+            Message::StickyCollisionsToggled => {
+                self.world_state.sticky_collisions = !self.world_state.sticky_collisions;
+                println!("Sticky collisions: {}", self.world_state.sticky_collisions);
+            }
+            Message::RandomWalkToggled => {
+                self.world_state.random_walk_step = if self.world_state.random_walk_step > 0.0 {0.0} else {2.0};
+                println!("Random walk step: {}", self.world_state.random_walk_step);
+            }
+            Message::AddSeedCircle => {
+                println!("Adding seed circle...");
+
+                let added = self.world_state.add_seed_circle_at_coords(
+                    (self.world_state.width / 2.0) as i32,
+                    (self.world_state.height / 2.0) as i32,
+                );
+
+                if let Err(error) = added {
+                    println!("{}", error);
+                }
+            }
+            Message::GravityToggled => {
+                self.world_state.gravity = if self.world_state.gravity != 0.0 {0.0} else {GRAVITY};
+                println!("Gravity: {}", self.world_state.gravity);
+            }
+            Message::WaterToggled => {
+                if self.world_state.fluid_regions.is_empty() {
+                    println!("Filling the lower third with water.");
+                    let water: FluidRegion = FluidRegion::below_water_line(
+                        self.world_state.width,
+                        self.world_state.height,
+                        self.world_state.height * 2.0 / 3.0,
+                        WATER_DENSITY,
+                        WATER_VISCOSITY,
+                    );
+                    self.world_state.add_fluid_region(water);
+                } else {
+                    println!("Draining the water.");
+                    self.world_state.remove_fluid_regions();
+                }
+            }
+            Message::PourFluid => {
+                println!("Pouring SPH fluid...");
+
+                self.world_state.add_fluid_block(
+                    (self.world_state.width / 4.0) as i32,
+                    (self.world_state.height / 8.0) as i32,
+                    20,
+                    12,
+                );
+            }
+            Message::FluidSurfaceToggled => {
+                self.world_state.render_fluid_as_surface = !self.world_state.render_fluid_as_surface;
+            }
+            Message::WindOff => {
+                self.world_state.flow_field = None;
+            }
+            Message::WindUniform => {
+                self.world_state.flow_field = Some(FlowField::Uniform { x_vel: 2.0, y_vel: 0.0 });
+            }
+            Message::WindVortex => {
+                self.world_state.flow_field = Some(FlowField::Vortex {
+                    x_pos: self.world_state.width / 2.0,
+                    y_pos: self.world_state.height / 2.0,
+                    strength: 3.0,
+                    core_radius: self.world_state.width / 6.0,
+                });
+            }
+            Message::WindShear => {
+                self.world_state.flow_field = Some(FlowField::Shear {
+                    rate: 4.0 / self.world_state.height,
+                    reference_y: self.world_state.height / 2.0,
+                });
+            }
+            Message::OpenScenario => {
+                if let Some(path) = self.answer(ui, Question::File { title: "Open scenario", pattern: "*.ron" }) {
+                    let opened: Result<(), SimulationError> = Scenario::load(&path)
+                        .and_then(|scenario| self.check_world_size(&scenario).map(|_| scenario))
+                        .and_then(|scenario| self.world_state.apply_scenario(scenario));
+
+                    match opened {
+                        Ok(()) => {
+                            println!("Opened {}, random seed is {}", path, self.world_state.seed);
+                            self.history.clear();
+                            self.reversibility_test = None;
+                            ui.set_title(&path);
+                            self.scenario_path = Some(path);
+                        }
+                        Err(error) => {
+                            let message: String = format!("Could not open {}: {}", path, error);
+                            println!("{}", message);
+                            ui.alert(&message);
+                        }
+                    }
+                }
+            }
+            Message::SaveScenario | Message::SaveScenarioAs => {
+                let path: Option<String> = match (&message, &self.scenario_path) {
+                    (Message::SaveScenario, Some(path)) => Some(path.clone()),
+                    _ => self.answer(ui, Question::File { title: "Save scenario as", pattern: "*.ron" }),
+                };
+
+                if let Some(path) = path {
+                    match self.world_state.to_scenario().save(&path) {
+                        Ok(()) => {
+                            println!("Saved {}", path);
+                            ui.set_title(&path);
+                            self.scenario_path = Some(path);
+                        }
+                        Err(error) => {
+                            let message: String = format!("Could not save {}: {}", path, error);
+                            println!("{}", message);
+                            ui.alert(&message);
+                        }
+                    }
+                }
+            }
+            Message::LoadScript => {
+                if let Some(path) = self.answer(ui, Question::File { title: "Choose script", pattern: "*.rhai" }) {
+                    match Script::load(&path, &mut self.world_state) {
+                        Ok(loaded_script) => {
+                            println!("Loaded script {}", path);
+                            self.script = Some(loaded_script);
+                        }
+                        Err(error) => {
+                            let message: String = format!("Could not load script {}: {}", path, error);
+                            println!("{}", message);
+                            ui.alert(&message);
+                        }
+                    }
+                }
+            }
+            Message::UnloadScript => {
+                self.script = None;
+            }
+            Message::StartRecording => {
+                self.reset_for_recording();
+                self.recording = Some(Recording::start(&self.world_state));
+                println!("Recording input...");
+            }
+            Message::StopRecording => {
+                if let Some(recording) = self.recording.take() {
+                    if let Some(path) = ui.answer(&Question::File { title: "Save recording as", pattern: "*.ron" }) {
+                        match recording.save(&path) {
+                            Ok(()) => println!("Saved recording of {} ticks to {}", recording.checksums.len(), path),
+                            Err(error) => {
+                                let message: String = format!("Could not save {}: {}", path, error);
+                                println!("{}", message);
+                                ui.alert(&message);
+                            }
+                        }
+                    }
+                }
+            }
+            Message::ReplayRecording => {
+                self.recording = None;
+
+                if let Some(path) = ui.answer(&Question::File { title: "Replay recording", pattern: "*.ron" }) {
+                    match self.start_replay(&path) {
+                        Ok(()) => println!("Replaying {}", path),
+                        Err(error) => {
+                            let message: String = format!("Could not replay {}: {}", path, error);
+                            println!("{}", message);
+                            ui.alert(&message);
+                        }
+                    }
+                }
+            }
+            Message::VideoRecordingToggled => {
+                if self.video.is_some() {
+                    self.stop_video(ui);
+                } else if let Some(path) = ui.answer(&Question::File { title: "Record video to", pattern: "*.{png,gif,y4m}" }) {
+                    let every: Option<String> = ui.answer(&Question::Text { prompt: "Capture every Nth step:", default: "1" });
+                    let default_size: String = format!("{}x{}", self.world_state.width, self.world_state.height);
+                    let size: Option<String> = ui.answer(&Question::Text { prompt: "Frame size:", default: &default_size });
+
+                    if let (Some(every), Some(size)) = (every, size) {
+                        let started = every
+                            .trim()
+                            .parse::<usize>()
+                            .map_err(|_| SimulationError::Video(String::from("The capture interval should be a whole number of steps.")))
+                            .and_then(|every| Ok((every, video::parse_size(&size)?)))
+                            .and_then(|(every, size)| {
+                                let options: VideoOptions = VideoOptions {
+                                    path: path.clone(),
+                                    every,
+                                    size: Some(size),
+                                    frames_per_second: video::DEFAULT_FRAMES_PER_SECOND,
+                                    caption: None,
+                                    timestamp: false,
+                                };
+
+                                VideoRecorder::start(options, self.world_state.width, self.world_state.height)
+                            });
+
+                        match started {
+                            Ok(recorder) => {
+                                println!("Recording video to {}", path);
+                                self.video = Some(recorder);
+                            }
+                            Err(error) => {
+                                let message: String = format!("Could not record video to {}: {}", path, error);
+                                println!("{}", message);
+                                ui.alert(&message);
+                            }
+                        }
+                    }
+                }
+            }
+            Message::ExportImage => {
+                let default_name: String = export::timestamped_file_name("png");
+
+                if let Some(path) = ui.answer(&Question::NewFile { title: "Export image", pattern: "*.{png,ppm}", default_name: &default_name }) {
+                    if let Some(text) = ui.answer(&Question::Text { prompt: "Resolution multiplier:", default: "1" }) {
+                        match text.trim().parse::<usize>() {
+                            Ok(scale) if (1..=export::MAX_EXPORT_SCALE).contains(&scale) => {
+                                match export::save_image(&self.rendered_view(scale), &path) {
+                                    Ok(()) => println!("Exported {}", path),
+                                    Err(error) => {
+                                        let message: String = format!("Could not export {}: {}", path, error);
+                                        println!("{}", message);
+                                        ui.alert(&message);
+                                    }
+                                }
+                            }
+                            _ => ui.alert(&format!("The resolution multiplier should be a whole number from 1 to {}.", export::MAX_EXPORT_SCALE)),
+                        }
+                    }
+                }
+            }
+            Message::ExportSvg => {
+                let default_name: String = export::timestamped_file_name("svg");
+
+                if let Some(path) = ui.answer(&Question::NewFile { title: "Export SVG", pattern: "*.svg", default_name: &default_name }) {
+                    match svg::save_svg(&self.world_state, &path) {
+                        Ok(()) => println!("Exported {}", path),
+                        Err(error) => {
+                            let message: String = format!("Could not export {}: {}", path, error);
+                            println!("{}", message);
+                            ui.alert(&message);
+                        }
+                    }
+                }
+            }
+            Message::CircleOpacity => {
+                let opacity: Option<f64> = self.world_state.selected_circle_mut().map(|circle| circle.opacity);
+
+                match opacity {
+                    Some(opacity) => {
+                        let default: String = opacity.to_string();
+
+                        if let Some(text) = self.answer(ui, Question::Text { prompt: "Opacity, from 0 to 1:", default: &default }) {
+                            match (text.trim().parse::<f64>(), self.world_state.selected_circle_mut()) {
+                                (Ok(opacity), Some(circle)) if (0.0..=1.0).contains(&opacity) => circle.opacity = opacity,
+                                _ => ui.alert("The opacity should be a number from 0 to 1."),
+                            }
+                        }
+                    }
+                    None => ui.alert("Select a circle with the right mouse button first."),
+                }
+            }
+            Message::CircleBlendMode(mode) => {
+                match self.world_state.selected_circle_mut() {
+                    Some(circle) => circle.blend_mode = mode,
+                    None => ui.alert("Select a circle with the right mouse button first."),
+                }
+            }
+            Message::RenameCircle => {
+                let selected: Option<(usize, String)> = self.world_state.selected_circle_mut().map(|circle| (circle.id, circle.name.clone()));
+
+                match selected {
+                    Some((id, name)) => {
+                        if let Some(text) = self.answer(ui, Question::Text { prompt: "Name:", default: &name }) {
+                            if let Err(error) = self.world_state.rename_circle(id, &text) {
+                                ui.alert(&error.to_string());
+                            }
+                        }
+                    }
+                    None => ui.alert("Select a circle with the right mouse button first."),
+                }
+            }
+            Message::CircleLabelsChanged(label) => {
+                self.world_state.circle_labels = label;
+            }
+            Message::DebugLayerToggled(layer) => {
+                self.debug_overlay.toggle(layer);
+            }
+            Message::TrailsToggled => {
+                self.world_state.trail_settings.enabled = !self.world_state.trail_settings.enabled;
+            }
+            Message::TrailStyleChanged(style) => {
+                self.world_state.trail_settings.style = style;
+            }
+            Message::TrailLength => {
+                let default: String = self.world_state.trail_settings.length.to_string();
+
+                if let Some(text) = self.answer(ui, Question::Text { prompt: "Positions kept in a trail:", default: &default }) {
+                    match text.trim().parse::<usize>() {
+                        Ok(length) if length > 1 => self.world_state.trail_settings.length = length,
+                        _ => ui.alert("The trail length should be a whole number of at least 2 positions."),
+                    }
+                }
+            }
+            Message::CircleTrailToggled => {
+                match self.world_state.selected_circle_mut() {
+                    Some(circle) => circle.leaves_trail = !circle.leaves_trail,
+                    None => ui.alert("Select a circle with the right mouse button first."),
+                }
+            }
+            Message::HeatmapToggled => {
+                self.world_state.heatmap_settings.enabled = !self.world_state.heatmap_settings.enabled;
+            }
+            Message::ResetHeatmap => {
+                self.world_state.heatmap.reset();
+            }
+            Message::HeatmapCellSize => {
+                let default: String = self.world_state.heatmap_settings.cell_size.to_string();
+
+                if let Some(text) = self.answer(ui, Question::Text { prompt: "Heatmap cell size:", default: &default }) {
+                    match text.trim().parse::<f64>() {
+                        Ok(cell_size) => {
+                            if let Err(error) = self.world_state.set_heatmap_cell_size(cell_size) {
+                                ui.alert(&error.to_string());
+                            }
+                        }
+                        Err(_) => ui.alert("The heatmap cell size should be a number of at least 1."),
+                    }
+                }
+            }
+            Message::HeatmapDecay => {
+                let default: String = self.world_state.heatmap_settings.decay.to_string();
+
+                if let Some(text) = self.answer(ui, Question::Text { prompt: "Fraction lost every step, from 0 to 1:", default: &default }) {
+                    match text.trim().parse::<f64>() {
+                        Ok(decay) if (0.0..=1.0).contains(&decay) => self.world_state.heatmap_settings.decay = decay,
+                        _ => ui.alert("The decay should be a number from 0 to 1."),
+                    }
+                }
+            }
+            Message::ExportHeatmapCsv => {
+                let default_name: String = export::timestamped_file_name("csv");
+
+                if let Some(path) = ui.answer(&Question::NewFile { title: "Export heatmap", pattern: "*.csv", default_name: &default_name }) {
+                    match self.world_state.heatmap.save_csv(&path) {
+                        Ok(()) => println!("Exported {}", path),
+                        Err(error) => {
+                            let message: String = format!("Could not export {}: {}", path, error);
+                            println!("{}", message);
+                            ui.alert(&message);
+                        }
+                    }
+                }
+            }
+            Message::WindFromFile => {
+                if let Some(path) = self.answer(ui, Question::File { title: "Choose flow field", pattern: "*.txt" }) {
+                    match FlowField::load_grid(&path, self.world_state.width, self.world_state.height) {
+                        Ok(flow_field) => self.world_state.flow_field = Some(flow_field),
+                        Err(error) => ui.alert(&format!("Could not load flow field {}: {}", path, error)),
+                    }
+                }
+            }
+            Message::FlowFieldToggled => {
+                self.world_state.show_flow_field = !self.world_state.show_flow_field;
+            }
+            Message::LogCollisionsToggled => {
+                self.log_collisions.set(!self.log_collisions.get());
+            }
+            Message::AddSensor(kind) => {
+                println!("Drag over the image to place the sensor.");
+                self.sensor_tool = Some(kind);
+            }
+            Message::ResetSensorCounters => {
+                self.world_state.reset_sensor_counters();
+            }
+            Message::RemoveSensors => {
+                self.world_state.remove_sensors();
+            }
+            Message::ThermostatChanged => {
+                let choice: Option<String> = self.answer(ui, Question::ThermostatChoice);
+
+                self.world_state.thermostat = match choice.and_then(|text| text.parse::<i32>().ok()) {
+                    Some(1) => Thermostat::VelocityRescaling,
+                    Some(2) => Thermostat::Berendsen { time_constant: 50.0 },
+                    Some(3) => Thermostat::Andersen { collision_frequency: 0.01 },
+                    _ => Thermostat::Off,
+                };
+            }
+            Message::TargetTemperatureChanged => {
+                let value: Option<String> = self.answer(ui, Question::TargetTemperature);
+
+                if let Some(temperature) = value.and_then(|text| text.parse::<f64>().ok()) {
+                    self.world_state.target_temperature = temperature;
+                }
+            }
+            Message::RestartWithSeed => {
+                let default: String = self.world_state.seed.to_string();
+
+                if let Some(text) = self.answer(ui, Question::Text { prompt: "Random seed:", default: &default }) {
+                    match text.trim().parse::<u64>() {
+                        Ok(seed) => {
+                            println!("Restarting with random seed {}", seed);
+                            self.world_state.restart_with_seed(seed);
+                            self.history.clear();
+                            self.history.resume();
+                            self.added_circle_id = None;
+                        }
+                        Err(_) => ui.alert("The random seed should be a non-negative whole number."),
+                    }
+                }
+            }
+            Message::RewindButEv => {
+                self.history.rewind();
+            }
+            Message::StepBackButEv => {
+                self.history.step_back(&mut self.world_state);
+            }
+            Message::ResumeButEv => {
+                self.history.resume();
+            }
+            Message::StepForwardButEv => {
+                self.history.step_forward(&mut self.world_state);
+            }
+            Message::TimelineMoved => {
+                let value: Option<String> = self.answer(ui, Question::TimelinePosition);
+
+                if let Some(position) = value.and_then(|text| text.parse::<usize>().ok()) {
+                    self.history.seek(position, &mut self.world_state);
+                }
+            }
+            Message::HistoryCapacity => {
+                let default: String = self.history.capacity.to_string();
+
+                if let Some(text) = self.answer(ui, Question::Text { prompt: "Number of ticks to keep:", default: &default }) {
+                    match text.trim().parse::<usize>() {
+                        Ok(capacity) => self.history.set_capacity(capacity),
+                        Err(_) => ui.alert("The buffer size should be a whole number of ticks."),
+                    }
+                }
+            }
+            Message::HistoryDeltaToggled => {
+                if self.history.mode == HistoryMode::Full {
+                    self.history.set_mode(HistoryMode::Delta { keyframe_interval: HISTORY_KEYFRAME_INTERVAL });
+                } else {
+                    self.history.set_mode(HistoryMode::Full);
+                }
+            }
+            Message::ReverseTime => {
+                println!("Reversing time.");
+                self.world_state.reverse_time();
+            }
+            Message::TestReversibility => {
+                if let Some(text) = self.answer(ui, Question::Text { prompt: "Steps to run in each direction:", default: "300" }) {
+                    match text.trim().parse::<usize>() {
+                        Ok(steps) => {
+                            self.history.resume();
+                            self.reversibility_test = Some(ReversibilityTest::start(&self.world_state, steps));
+                        }
+                        Err(_) => ui.alert("The number of steps should be a whole number."),
+                    }
+                }
+            }
+            Message::ThermaliseButEv => {
+                println!("Drawing velocities from Maxwell-Boltzmann distribution...");
+
+                self.world_state.set_maxwell_boltzmann_velocities(self.world_state.target_temperature);
+            }
+            Message::WBev => {
+                println!("Change background to White.");
+                self.change_background(Colour::White, ui);
+            }
+            Message::LGBev => {
+                println!("Change background to Light Grey.");
+                self.change_background(Colour::LightGrey, ui);
+            }
+            Message::GBev => {
+                println!("Change background to Grey.");
+                self.change_background(Colour::Grey, ui);
+            }
+            Message::BBev => {
+                println!("Change background to Black.");
+                self.change_background(Colour::Black, ui);
+            }
+            Message::BackgroundColour => {
+                let pixels: &[u8] = self.world_state.background_pixels();
+                let current: RGBColor = RGBColor { r: pixels[0], g: pixels[1], b: pixels[2] };
+
+                if let Some(text) = self.answer(ui, Question::Colour { title: "Background colour", default: current }) {
+                    match parse_colour(&text) {
+                        Some(color) => self.change_background(Colour::Rgb(color), ui),
+                        None => ui.alert("The colour should be written as #rrggbb."),
+                    }
+                }
+            }
+            Message::LoadBackgroundImage(fit) => {
+                if let Some(path) = self.answer(ui, Question::File { title: "Choose background image", pattern: "*.{png,jpg,jpeg,bmp}" }) {
+                    if let Err(error) = self.world_state.load_background_image(BackgroundImage { path, fit }) {
+                        println!("{}", error);
+                        ui.alert(&error.to_string());
+                    }
+                }
+            }
+            Message::Tick => {
+                self.ticks += 1;
+                self.history.tick(&mut self.world_state);
+
+                let collisions: Vec<CollisionEvent> = self.world_state.take_collision_events();
+                self.debug_overlay.remember_contacts(&collisions, self.world_state.global_time);
+
+                // hooks only run for steps simulated live, not while replaying history
+                if self.history.playback == Playback::Live {
+                    if let Some(running_script) = &mut self.script {
+                        if let Err(error) = running_script.after_step(&mut self.world_state, &collisions) {
+                            println!("{}, script stopped", error);
+                            ui.alert(&error.to_string());
+                            self.script = None;
+                        }
+                    }
+                }
+
+                for event in self.world_state.take_sensor_events() {
+                    println!("{:>5}: circle {:>3} {:?} sensor {}", event.global_time, event.circle_id, event.kind, event.sensor_id);
+                }
+
+                if let Some(recorder) = &mut self.video {
+                    if let Err(error) = recorder.capture(&self.world_state.get_rendered_view(), self.world_state.global_time) {
+                        let message: String = format!("Could not write {}: {}", recorder.path(), error);
+                        println!("{}, video stopped", message);
+                        ui.alert(&message);
+                        self.video = None;
+                    }
+                }
+
+                if let Some(test) = &mut self.reversibility_test {
+                    if let Some(report) = test.after_step(&mut self.world_state) {
+                        for (id, error) in report.errors.iter() {
+                            println!("circle {:>4}: positional error {:>12.6}", id, error);
+                        }
+                        println!("{}", report.summary());
+                        ui.inform(&report.summary());
+
+                        self.reversibility_test = None;
+                    }
+                }
+            }
+            Message::MouseDown(x, y, button) => {
+                println!("The image was clicked at coordinates x={}, y={}", x, y);
+
+                if button == MouseButton::Right {
+                    self.world_state.select_circle(x, y);
+                }
+
+                let mut is_handled_by_script: bool = false;
+
+                if button == MouseButton::Left && self.sensor_tool.is_none() {
+                    if let Some(running_script) = &mut self.script {
+                        match running_script.click(&mut self.world_state, x as f64, y as f64) {
+                            Ok(is_handled) => is_handled_by_script = is_handled,
+                            Err(error) => {
+                                println!("{}, script stopped", error);
+                                ui.alert(&error.to_string());
+                                self.script = None;
+                            }
+                        }
+                    }
+                }
+
+                if is_handled_by_script {
+                    // the script decided what the click does
+                } else if button == MouseButton::Left && self.sensor_tool.is_some() {
+                    self.new_circle_coord.set_i(x, y);
+                    self.cursor_coord.set_i(x, y);
+                    self.is_placing_sensor = true;
+                } else if button == MouseButton::Left {
+                    match self.added_circle_id.take() {
+                        // the second click aims the new circle, there is nothing to aim if it did not fit
+                        None => match self.world_state.add_random_circle_at_coords(x, y, 0.0, 0.0) {
+                            Ok(id) => {
+                                self.new_circle_coord.set_i(x, y);
+                                self.cursor_coord.set_i(x, y);
+                                self.added_circle_id = Some(id);
+                            }
+                            Err(error) => println!("{}", error),
+                        },
+                        // the circle may have been removed or stuck to a cluster since the first click
+                        Some(id) => {
+                            self.cursor_coord.set_i(x, y);
+
+                            let x_vel: f64 = (self.cursor_coord.x - self.new_circle_coord.x) / 100.0;
+                            let y_vel: f64 = (self.cursor_coord.y - self.new_circle_coord.y) / 100.0;
+
+                            if let Some(circle) = self.world_state.circles.iter_mut().find(|circle| circle.id == id && circle.cluster_id.is_none()) {
+                                circle.x_vel = x_vel;
+                                circle.y_vel = y_vel;
+                            }
+                        }
+                    }
+                }
+            }
+            Message::MouseDrag(x, y) => {
+                if self.is_placing_sensor {
+                    self.cursor_coord.set_i(x, y);
+                } else {
+                    self.world_state.accelerate_selected_to_position(x as f64, y as f64);
+                }
+            }
+            Message::MouseReleased(x, y, button) => {
+                if let (true, Some(kind), MouseButton::Left) = (self.is_placing_sensor, self.sensor_tool, button) {
+                    self.cursor_coord.set_i(x, y);
+
+                    match SensorShape::from_drag(kind, self.new_circle_coord.x, self.new_circle_coord.y, self.cursor_coord.x, self.cursor_coord.y) {
+                        Some(shape) => {
+                            self.world_state.add_sensor(shape);
+                        }
+                        None => println!("Sensor too small, not placed."),
+                    }
+
+                    self.sensor_tool = None;
+                    self.is_placing_sensor = false;
+                }
+            }
+            Message::MouseMove(x, y) => {
+                // println!("There was Move event at coordinates x={}, y={}", x, y);
+                if self.added_circle_id.is_some() {
+                    // draw a line from circle center to current mouse cursor
+                    self.cursor_coord.set_i(x, y);
+                }
+            }
+        };
+    }
+
+    // the world as shown in the window, with the debug layer and the aiming line while a circle or sensor is being placed
+    pub fn rendered_view(&self, scale: usize) -> RGBCanvas {
+        let mut view: RGBCanvas = self.world_state.get_rendered_view_scaled(scale);
+
+        self.debug_overlay.put_on_canvas(&self.world_state, &mut view, scale as f64);
+
+        if self.added_circle_id.is_some() || self.is_placing_sensor {
+            let factor: f64 = scale as f64;
+
+            // a new circle is launched towards the cursor, a sensor is stretched to it
+            let style: LineStyle = if self.added_circle_id.is_some() {
+                LineStyle::new(AIMING_LINE_COLOR).thickness(1.5 * factor).arrow(8.0 * factor)
+            } else {
+                LineStyle::new(AIMING_LINE_COLOR).thickness(factor).dashed(6.0 * factor, 4.0 * factor)
+            };
+
+            view.draw_line(
+                self.new_circle_coord.x * factor,
+                self.new_circle_coord.y * factor,
+                self.cursor_coord.x * factor,
+                self.cursor_coord.y * factor,
+                &style,
+            );
+        }
+
+        return view;
+    }
+
+    pub fn stop_video(&mut self, ui: &mut dyn UserInterface) {
+        if let Some(recorder) = self.video.take() {
+            let path: String = String::from(recorder.path());
+
+            match recorder.finish() {
+                Ok(frames) => println!("Wrote {} frames to {}", frames, path),
+                Err(error) => {
+                    let message: String = format!("Could not finish {}: {}", path, error);
+                    println!("{}", message);
+                    ui.alert(&message);
+                }
+            }
+        }
+    }
+
+    pub fn start_replay(&mut self, path: &str) -> Result<(), SimulationError> {
+        let recording: Recording = Recording::load(path)?;
+
+        self.check_world_size(&recording.initial)?;
+        self.world_state.apply_scenario(recording.initial.clone())?;
+        self.reset_for_recording();
+        self.replay = Some(Replay::new(recording));
+
+        return Ok(());
+    }
+
+    // the window shows the world pixel for pixel, so it can only open worlds of its own size
+    fn check_world_size(&self, scenario: &Scenario) -> Result<(), SimulationError> {
+        if scenario.width != self.world_state.width || scenario.height != self.world_state.height {
+            return Err(SimulationError::Scenario(format!(
+                "The scenario world is {} x {}, this window shows {} x {}",
+                scenario.width, scenario.height, self.world_state.width, self.world_state.height,
+            )));
+        }
+
+        return Ok(());
+    }
+
+    fn change_background(&mut self, colour: Colour, ui: &mut dyn UserInterface) {
+        let background: Vec<u8> = generate_image_background(
+            self.world_state.width as i32,
+            self.world_state.height as i32,
+            colour,
+        );
+
+        if let Err(error) = self.world_state.replace_background(background) {
+            println!("{}", error);
+            ui.alert(&error.to_string());
+        }
+    }
+}
+
+// #rrggbb, as the colour question is answered
+fn parse_colour(text: &str) -> Option<RGBColor> {
+    let digits: &str = text.trim().strip_prefix('#')?;
+
+    if digits.len() != 6 || !digits.is_ascii() {
+        return None;
+    }
+
+    let channel = |start: usize| u8::from_str_radix(&digits[start..start + 2], 16).ok();
+
+    return Some(RGBColor {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+    });
+}
+
+pub fn generate_image_background(width: i32, height: i32, colour: Colour) -> Vec<u8> {
+    let num_pix: usize = (width * height) as usize;
+
+    let data_array: Vec<u8>;
+
+    match colour {
+        Colour::Black => data_array = vec![0; num_pix * 3],
+        Colour::Grey => data_array = vec![127; num_pix * 3],
+        Colour::LightGrey => data_array = vec![191; num_pix * 3],
+        Colour::White => data_array = vec![255; num_pix * 3],
+        Colour::Rgb(color) => data_array = [color.r, color.g, color.b].repeat(num_pix),
+    }
+
+    return data_array;
+}
@@ -0,0 +1,171 @@
+// Smoothed-particle hydrodynamics: circles marked as fluid particles behave like a liquid
+
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{broadphase::UniformGrid, circle::Circle, common_structs::{RGBCanvas, RGBColor}, drawing::Canvas};
+
+const SURFACE_THRESHOLD: f64 = 0.5; // metaball field value at the fluid surface
+const SURFACE_SPREAD: f64 = 3.0; // how far (in particle radii) each particle contributes to the metaball field
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SphParameters {
+    pub smoothing_length: f64,
+    pub rest_density: f64,
+    pub stiffness: f64,
+    pub viscosity: f64,
+    pub surface_tension: f64,
+}
+
+impl SphParameters {
+    pub fn new() -> SphParameters {
+        return SphParameters {
+            smoothing_length: 16.0,
+            rest_density: 0.012, // roughly one unit mass per particle spacing squared
+            stiffness: 40.0,
+            viscosity: 0.1,
+            surface_tension: 0.05,
+        };
+    }
+
+    // poly6 kernel, used for density and cohesion
+    fn kernel(&self, distance_squared: f64) -> f64 {
+        let h: f64 = self.smoothing_length;
+
+        if distance_squared >= h * h {
+            return 0.0;
+        }
+
+        return 4.0 / (PI * h.powi(8)) * (h * h - distance_squared).powi(3);
+    }
+
+    // magnitude of the spiky kernel gradient, used for pressure
+    fn kernel_gradient(&self, distance: f64) -> f64 {
+        let h: f64 = self.smoothing_length;
+
+        if distance >= h {
+            return 0.0;
+        }
+
+        return -30.0 / (PI * h.powi(5)) * (h - distance) * (h - distance);
+    }
+
+    // laplacian of the viscosity kernel
+    fn kernel_laplacian(&self, distance: f64) -> f64 {
+        let h: f64 = self.smoothing_length;
+
+        if distance >= h {
+            return 0.0;
+        }
+
+        return 40.0 / (PI * h.powi(5)) * (h - distance);
+    }
+
+    // returns (x, y) acceleration for every index in fluid_indices, neighbours are looked up in the grid
+    pub fn compute_accelerations(&self, circles: &Vec<Circle>, fluid_indices: &Vec<usize>, grid: &UniformGrid) -> Vec<(f64, f64)> {
+        let h: f64 = self.smoothing_length;
+
+        let mut densities: Vec<f64> = vec![0.0; circles.len()];
+        let mut pressures: Vec<f64> = vec![0.0; circles.len()];
+
+        for &i in fluid_indices.iter() {
+            let mut density: f64 = 0.0;
+
+            for j in grid.query(circles[i].x_pos, circles[i].y_pos, h) {
+                let rel_x: f64 = circles[i].x_pos - circles[j].x_pos;
+                let rel_y: f64 = circles[i].y_pos - circles[j].y_pos;
+
+                density += circles[j].mass * self.kernel(rel_x * rel_x + rel_y * rel_y);
+            }
+
+            densities[i] = density;
+            // no negative pressure, cohesion is handled by surface tension instead
+            pressures[i] = f64::max(self.stiffness * (density - self.rest_density), 0.0);
+        }
+
+        let mut accelerations: Vec<(f64, f64)> = Vec::with_capacity(fluid_indices.len());
+
+        for &i in fluid_indices.iter() {
+            let mut a_x: f64 = 0.0;
+            let mut a_y: f64 = 0.0;
+
+            for j in grid.query(circles[i].x_pos, circles[i].y_pos, h) {
+                if i == j {
+                    continue;
+                }
+
+                let rel_x: f64 = circles[i].x_pos - circles[j].x_pos;
+                let rel_y: f64 = circles[i].y_pos - circles[j].y_pos;
+                let distance_squared: f64 = rel_x * rel_x + rel_y * rel_y;
+
+                if distance_squared >= h * h || distance_squared == 0.0 {
+                    continue;
+                }
+
+                let distance: f64 = f64::sqrt(distance_squared);
+                let density_product: f64 = densities[i] * densities[j];
+
+                // pressure pushes apart
+                let pressure_term: f64 = -circles[j].mass * (pressures[i] + pressures[j]) / (2.0 * density_product) * self.kernel_gradient(distance);
+                a_x += pressure_term * rel_x / distance;
+                a_y += pressure_term * rel_y / distance;
+
+                // viscosity evens out velocities of neighbours
+                let viscosity_term: f64 = self.viscosity * circles[j].mass / density_product * self.kernel_laplacian(distance);
+                a_x += viscosity_term * (circles[j].x_vel - circles[i].x_vel);
+                a_y += viscosity_term * (circles[j].y_vel - circles[i].y_vel);
+
+                // surface tension pulls neighbours together
+                let cohesion_term: f64 = -self.surface_tension * circles[j].mass * self.kernel(distance_squared) / densities[i];
+                a_x += cohesion_term * rel_x;
+                a_y += cohesion_term * rel_y;
+            }
+
+            accelerations.push((a_x, a_y));
+        }
+
+        return accelerations;
+    }
+}
+
+// draws fluid particles as one continuous surface: sums a metaball field and fills everything above the threshold
+pub fn put_fluid_surface_on_canvas(circles: &Vec<Circle>, canvas: &mut RGBCanvas, color: RGBColor) {
+    let width: i32 = canvas.width as i32;
+    let height: i32 = canvas.height as i32;
+
+    let mut field: Vec<f64> = vec![0.0; (width * height) as usize];
+
+    for circle in circles.iter().filter(|circle| circle.is_fluid) {
+        let spread: f64 = circle.radius * SURFACE_SPREAD;
+
+        let box_lx: i32 = i32::max((circle.x_pos - spread) as i32, 0);
+        let box_hx: i32 = i32::min((circle.x_pos + spread) as i32 + 1, width);
+        let box_ly: i32 = i32::max((circle.y_pos - spread) as i32, 0);
+        let box_hy: i32 = i32::min((circle.y_pos + spread) as i32 + 1, height);
+
+        let mut distance_squared: f64;
+        let mut falloff: f64;
+
+        for j in box_ly..box_hy {
+            for i in box_lx..box_hx {
+                distance_squared = (i as f64 - circle.x_pos) * (i as f64 - circle.x_pos) + (j as f64 - circle.y_pos) * (j as f64 - circle.y_pos);
+
+                if distance_squared < spread * spread {
+                    falloff = 1.0 - distance_squared / (spread * spread);
+                    field[(j * width + i) as usize] += falloff * falloff;
+                }
+            }
+        }
+    }
+
+    let mut d: f64; // coverage of a pixel, blended over a narrow band around the threshold for a smooth edge
+
+    for j in 0..height {
+        for i in 0..width {
+            d = f64::clamp((field[(j * width + i) as usize] - SURFACE_THRESHOLD) * 10.0 + 0.5, 0.0, 1.0);
+
+            canvas.blend_pixel(i, j, color, d);
+        }
+    }
+}
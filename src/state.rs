@@ -1,43 +1,207 @@
 // here, the object describing the state
 
-use rand::{random, Rng};
+use std::collections::HashMap;
 
-use crate::{circle::Circle, common_structs::{RGBCanvas, RGBColor}};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    background::BackgroundImage,
+    broadphase::UniformGrid,
+    circle::{Circle, CircleLabel},
+    cluster::Cluster,
+    common_structs::{BlendMode, RGBACanvas, RGBCanvas, RGBColor},
+    error::SimulationError,
+    events::{CollisionEvent, CollisionObserver, SensorEvent},
+    flow_field::FlowField,
+    fluid::FluidRegion,
+    heatmap::{Heatmap, HeatmapSettings},
+    history::StateSnapshot,
+    scenario::{SavedBackground, SavedSensor, Scenario, Settings, SCENARIO_VERSION},
+    sensor::{Sensor, SensorShape},
+    sph::{self, SphParameters},
+    thermostat::{self, Thermostat},
+    trails::{TrailSettings, Trails},
+};
+
+const MAX_WORLD_SIZE: f64 = 4096.0; // pixels on each side of a loaded world
+const FLUID_PARTICLE_RADIUS: f64 = 4.0;
+const FLUID_PARTICLE_MASS: f64 = 1.0;
+const FLUID_COLOR: RGBColor = RGBColor {
+    r: 60,
+    g: 130,
+    b: 230,
+};
+const SENSOR_COLOR: RGBColor = RGBColor {
+    r: 230,
+    g: 120,
+    b: 20,
+};
 
 
 pub struct State {
     pub width: f64, //world width
     pub height: f64, //world height
     background: Vec<u8>, //array containing rgb values for background image
+    background_image: Option<BackgroundImage>, // the file the background was read from, if any
     pub circles: Vec<Circle>,
+    pub clusters: Vec<Cluster>,
     pub selected_circle_index: usize,
     pub has_selected_circle: bool,
     pub global_time: usize,
+    pub sticky_collisions: bool, // touching circles stick together into rigid clusters
+    pub random_walk_step: f64, // when positive, free circles get a new random velocity of this magnitude every step
+    pub gravity: f64, // downward acceleration per step
+    pub fluid_regions: Vec<FluidRegion>,
+    pub sph: SphParameters,
+    pub render_fluid_as_surface: bool, // draw SPH particles as one metaball surface instead of separate discs
+    pub flow_field: Option<FlowField>,
+    pub flow_drag: f64, // drag per unit of cross-section and relative velocity
+    pub show_flow_field: bool,
+    pub circle_labels: CircleLabel,
+    pub trail_settings: TrailSettings,
+    pub trails: Trails, // recent positions of every circle, kept while trails are shown
+    pub heatmap_settings: HeatmapSettings,
+    pub heatmap: Heatmap, // accumulated while shown, replaced whenever its grid changes
+    pub thermostat: Thermostat,
+    pub target_temperature: f64,
+    pub seed: u64, // seed of the generator below, same seed and same inputs give the same simulation
+    rng: ChaCha8Rng, // every random decision in the simulation is drawn from here
+    next_circle_id: usize,
+    next_cluster_id: usize,
+    collision_events: Vec<CollisionEvent>, // collisions of the steps since the last take_collision_events
+    collision_observers: Vec<Box<dyn CollisionObserver>>,
+    pub sensors: Vec<Sensor>,
+    sensor_events: Vec<SensorEvent>, // sensor events of the steps since the last take_sensor_events
+    next_sensor_id: usize,
+}
+
+struct Pair {
+    i: usize,
+    j: usize,
+}
+
+// a body is either a single free circle (index into circles) or a whole cluster (index into clusters)
+#[derive(Clone, Copy, PartialEq)]
+enum Body {
+    Free(usize),
+    Clustered(usize),
 }
 
 impl State {
     pub fn new(width: i32, height: i32) -> State {
+        return State::new_with_seed(width, height, rand::random());
+    }
+
+    pub fn new_with_seed(width: i32, height: i32, seed: u64) -> State {
         return State {
             width: width as f64,
             height: height as f64,
             background: State::create_background(width, height),
+            background_image: None,
             circles: Vec::<Circle>::new(),
+            clusters: Vec::<Cluster>::new(),
             selected_circle_index: 0,
             has_selected_circle: false,
             global_time: 0,
+            sticky_collisions: false,
+            random_walk_step: 0.0,
+            gravity: 0.0,
+            fluid_regions: Vec::<FluidRegion>::new(),
+            sph: SphParameters::new(),
+            render_fluid_as_surface: false,
+            circle_labels: CircleLabel::Off,
+            trail_settings: TrailSettings::default(),
+            trails: Trails::default(),
+            heatmap_settings: HeatmapSettings::default(),
+            heatmap: Heatmap::new(width as f64, height as f64, HeatmapSettings::default().cell_size),
+            flow_field: None,
+            flow_drag: 0.05,
+            show_flow_field: false,
+            thermostat: Thermostat::Off,
+            target_temperature: 500.0,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            next_circle_id: 0,
+            next_cluster_id: 0,
+            collision_events: Vec::<CollisionEvent>::new(),
+            collision_observers: Vec::new(),
+            sensors: Vec::<Sensor>::new(),
+            sensor_events: Vec::<SensorEvent>::new(),
+            next_sensor_id: 0,
         };
     }
 
     pub fn get_rendered_view(&self) -> RGBCanvas {
-        let mut rendered_scene = RGBCanvas::new(self.width, self.height);
-        
-        for i in 0..self.background.len() {
-            rendered_scene.data[i] = self.background[i];
+        return self.get_rendered_view_scaled(1);
+    }
+
+    // renders the world with `scale` pixels per unit, so that exported images stay sharp;
+    // only the background bitmap is enlarged pixel by pixel
+    pub fn get_rendered_view_scaled(&self, scale: usize) -> RGBCanvas {
+        let factor: f64 = scale as f64;
+        let mut rendered_scene = RGBCanvas::new(self.width as usize * scale, self.height as usize * scale);
+
+        if self.heatmap_settings.enabled {
+            self.heatmap.put_on_canvas(&mut rendered_scene, factor);
+        } else if scale == 1 {
+            rendered_scene.data.copy_from_slice(&self.background);
+        } else {
+            let width: usize = self.width as usize;
+            let scaled_width: usize = width * scale;
+
+            for j in 0..rendered_scene.height {
+                for i in 0..scaled_width {
+                    let source: usize = ((j / scale) * width + i / scale) * 3;
+                    let target: usize = (j * scaled_width + i) * 3;
+
+                    rendered_scene.data[target..target + 3].copy_from_slice(&self.background[source..source + 3]);
+                }
+            }
         }
 
-        for i in 0..self.circles.len() {
-            // self.circles[i].put_on_canvas(&mut rendered_scene);
-            self.circles[i].put_on_canvas_smoothed(&mut rendered_scene);
+        if self.show_flow_field {
+            if let Some(flow_field) = &self.flow_field {
+                flow_field.put_on_canvas(&mut rendered_scene, factor);
+            }
+        }
+
+        // translucent layers keep their own transparency and are composited onto the scene once they are complete
+        if self.trail_settings.enabled {
+            let mut trail_layer: RGBACanvas = RGBACanvas::new(rendered_scene.width, rendered_scene.height);
+            self.trails.put_on_canvas(&self.circles, &mut trail_layer, &self.trail_settings, factor);
+            rendered_scene.composite(&trail_layer, BlendMode::Normal);
+        }
+
+        let circles: Vec<Circle> = self.circles.iter().map(|circle| circle.scaled(factor)).collect();
+
+        if self.render_fluid_as_surface {
+            sph::put_fluid_surface_on_canvas(&circles, &mut rendered_scene, FLUID_COLOR);
+        }
+
+        for circle in circles.iter() {
+            if !(self.render_fluid_as_surface && circle.is_fluid) {
+                circle.put_on_canvas_smoothed(&mut rendered_scene);
+            }
+        }
+
+        if !self.fluid_regions.is_empty() {
+            let mut fluid_layer: RGBACanvas = RGBACanvas::new(rendered_scene.width, rendered_scene.height);
+
+            for region in self.fluid_regions.iter() {
+                region.scaled(factor).put_on_canvas(&mut fluid_layer);
+            }
+
+            rendered_scene.composite(&fluid_layer, BlendMode::Normal);
+        }
+
+        for sensor in self.sensors.iter() {
+            sensor.put_on_canvas(&mut rendered_scene, factor);
+        }
+
+        // fluid particles are too many and too small to be labelled
+        for circle in circles.iter().filter(|circle| !circle.is_fluid) {
+            circle.put_label_on_canvas(&mut rendered_scene, self.circle_labels, scale);
         }
 
         return rendered_scene;
@@ -45,19 +209,139 @@ impl State {
 
 /////////////////////////////////////////////////////////
     
-    pub fn add_circle(&mut self, circle: Circle) {
+    // returns the id given to the circle; its name is taken as a prefix and made unique, e.g. "Circle 12"
+    pub fn add_circle(&mut self, mut circle: Circle) -> Result<usize, SimulationError> {
         // ensure that new circle is not on top of another
         let is_on_top = Circle::check_on_top(&circle, &self.circles, 999999);
 
-        if !is_on_top {
-            self.circles.push(circle);
-        } else {
-            println!("trying to put circle on top of another one");
+        if is_on_top {
+            return Err(SimulationError::CircleOverlap { x: circle.x_pos, y: circle.y_pos });
+        }
+
+        circle.id = self.next_circle_id;
+        circle.cluster_id = None;
+        circle.name = self.unique_circle_name(&circle.name, circle.id);
+        self.next_circle_id += 1;
+
+        self.circles.push(circle);
+
+        return Ok(self.next_circle_id - 1);
+    }
+
+    // the prefix followed by the first number from `number` on that no circle is called yet
+    fn unique_circle_name(&self, prefix: &str, number: usize) -> String {
+        let mut number: usize = number;
+
+        loop {
+            let name: String = format!("{} {}", prefix, number);
+
+            if !self.circles.iter().any(|circle| circle.name == name) {
+                return name;
+            }
+
+            number += 1;
+        }
+    }
+
+    pub fn rename_circle(&mut self, id: usize, name: &str) -> Result<(), SimulationError> {
+        let name: &str = name.trim();
+
+        if name.is_empty() {
+            return Err(SimulationError::InvalidName { name: String::from(name), reason: "the name is empty" });
+        }
+
+        if self.circles.iter().any(|circle| circle.name == name && circle.id != id) {
+            return Err(SimulationError::InvalidName { name: String::from(name), reason: "another circle has that name" });
         }
+
+        return match self.circles.iter_mut().find(|circle| circle.id == id) {
+            Some(circle) => {
+                circle.name = String::from(name);
+                Ok(())
+            }
+            None => Err(SimulationError::UnknownCircle(id)),
+        };
+    }
+
+    pub fn add_random_circle_at_coords(&mut self, x: i32, y: i32, x_vel: f64, y_vel: f64) -> Result<usize, SimulationError> {
+        let new_circle: Circle = self.create_random_circle(x, y, x_vel, y_vel);
+
+        return self.add_circle(new_circle);
+    }
+
+    // circle with random colours, and random size unless (radius, mass) is given
+    pub fn add_circle_at(&mut self, x: f64, y: f64, x_vel: f64, y_vel: f64, size: Option<(f64, f64)>) -> Result<usize, SimulationError> {
+        let mut new_circle: Circle = self.create_random_circle(0, 0, x_vel, y_vel);
+
+        new_circle.x_pos = x;
+        new_circle.y_pos = y;
+
+        if let Some((radius, mass)) = size {
+            new_circle.radius = radius;
+            new_circle.mass = mass;
+        }
+
+        return self.add_circle(new_circle);
     }
 
-    pub fn add_random_circle_at_coords(&mut self, x: i32, y: i32, x_vel: f64, y_vel: f64) {
-        let mut rng = rand::thread_rng();
+    // seed circle is fixed in place, everything that sticks to it becomes fixed as well
+    pub fn add_seed_circle_at_coords(&mut self, x: i32, y: i32) -> Result<usize, SimulationError> {
+        let new_circle: Circle = self.create_random_circle(x, y, 0.0, 0.0);
+        let id: usize = self.add_circle(new_circle)?;
+        let index: usize = self.circles.len() - 1;
+
+        let circle: &Circle = &self.circles[index];
+        let seed_cluster: Cluster = Cluster::from_members(
+            self.next_cluster_id,
+            &vec![(circle.id, circle.x_pos, circle.y_pos, circle.radius, circle.mass)],
+            0.0,
+            0.0,
+            0.0,
+            true,
+        );
+
+        self.circles[index].cluster_id = Some(seed_cluster.id);
+        self.next_cluster_id += 1;
+        self.clusters.push(seed_cluster);
+
+        return Ok(id);
+    }
+
+    // fills a block of columns x rows fluid particles, with its top left corner at (x, y)
+    pub fn add_fluid_block(&mut self, x: i32, y: i32, columns: usize, rows: usize) {
+        let spacing: f64 = FLUID_PARTICLE_RADIUS * 2.0 + 1.0;
+        let mut number_left_out: usize = 0;
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let mut particle: Circle = Circle::new(
+                    String::from("Fluid"),
+                    x as f64 + FLUID_PARTICLE_RADIUS + column as f64 * spacing,
+                    y as f64 + FLUID_PARTICLE_RADIUS + row as f64 * spacing,
+                    0.0,
+                    0.0,
+                    FLUID_PARTICLE_RADIUS,
+                    1.0,
+                    FLUID_PARTICLE_MASS,
+                    FLUID_COLOR,
+                    FLUID_COLOR,
+                );
+
+                particle.is_fluid = true;
+
+                if self.add_circle(particle).is_err() {
+                    number_left_out += 1;
+                }
+            }
+        }
+
+        if number_left_out > 0 {
+            println!("{} fluid particles would overlap circles and were left out", number_left_out);
+        }
+    }
+
+    fn create_random_circle(&mut self, x: i32, y: i32, x_vel: f64, y_vel: f64) -> Circle {
+        let rng: &mut ChaCha8Rng = &mut self.rng;
 
         let mut new_circle: Circle = Circle::new(
             String::from("Circle"), 
@@ -69,20 +353,20 @@ impl State {
             rng.gen_range(3.0..13.0),
             1.0,
             RGBColor {
-                r: random(),
-                g: random(),
-                b: random(),
+                r: rng.gen(),
+                g: rng.gen(),
+                b: rng.gen(),
             },
             RGBColor {
-                r: random(),
-                g: random(),
-                b: random(),
+                r: rng.gen(),
+                g: rng.gen(),
+                b: rng.gen(),
             },
         );
 
         new_circle.mass = new_circle.radius * new_circle.radius;
 
-        self.add_circle(new_circle);
+        return new_circle;
     }
 
     pub fn select_circle(&mut self, x: i32, y: i32) {
@@ -116,68 +400,983 @@ impl State {
         self.has_selected_circle = is_selected;
     }
 
+    // removes the selected circle, or the whole cluster it is part of
     pub fn remove_circle(&mut self) {
         let mut new_circles_array: Vec<Circle> = Vec::new();
 
         if self.has_selected_circle {
+            let selected_cluster_id: Option<usize> = self.circles[self.selected_circle_index].cluster_id;
+
             for i in 0..self.circles.len() {
-                if i != self.selected_circle_index {
+                let is_in_selected_cluster: bool =
+                    selected_cluster_id.is_some() && self.circles[i].cluster_id == selected_cluster_id;
+
+                if i != self.selected_circle_index && !is_in_selected_cluster {
                     new_circles_array.push(self.circles[i].clone());
                 }
             }
 
+            if let Some(cluster_id) = selected_cluster_id {
+                self.clusters.retain(|cluster| cluster.id != cluster_id);
+            }
+
             self.circles = new_circles_array;
         }
         
         self.has_selected_circle = false;
     }
 
-    pub fn replace_background(&mut self, new_background: Vec<u8>) {
-        if new_background.len() == self.background.len() {
-            self.background = new_background;
-        } else {
-            panic!("Error replacing background: The new background should be the same size as old one!");
+    // removes the circle with the given id, or the whole cluster it is part of; clears the selection
+    pub fn remove_circle_by_id(&mut self, id: usize) {
+        if let Some(index) = self.circles.iter().position(|circle| circle.id == id) {
+            self.selected_circle_index = index;
+            self.has_selected_circle = true;
+            self.remove_circle();
+        }
+    }
+
+    // a force acting for one step at the circle's centre; a clustered circle pushes and turns its whole cluster
+    pub fn apply_force(&mut self, id: usize, force_x: f64, force_y: f64) {
+        if let Some(index) = self.circles.iter().position(|circle| circle.id == id) {
+            let (x, y) = (self.circles[index].x_pos, self.circles[index].y_pos);
+            let body: Body = self.body_of(index);
+
+            self.apply_impulse_to_body(body, x, y, force_x, force_y);
+            self.sync_cluster_circles();
+        }
+    }
+
+    pub fn selected_circle_mut(&mut self) -> Option<&mut Circle> {
+        if !self.has_selected_circle {
+            return None;
+        }
+
+        return self.circles.get_mut(self.selected_circle_index);
+    }
+
+    // negates every velocity, running forward afterwards retraces the motion
+    pub fn reverse_time(&mut self) {
+        for circle in self.circles.iter_mut() {
+            circle.x_vel = -circle.x_vel;
+            circle.y_vel = -circle.y_vel;
+        }
+
+        for cluster in self.clusters.iter_mut() {
+            cluster.x_vel = -cluster.x_vel;
+            cluster.y_vel = -cluster.y_vel;
+            cluster.angular_vel = -cluster.angular_vel;
+        }
+    }
+
+    pub fn take_snapshot(&self) -> StateSnapshot {
+        return StateSnapshot {
+            global_time: self.global_time,
+            circles: self.circles.clone(),
+            clusters: self.clusters.clone(),
+            selected_circle_index: self.selected_circle_index,
+            has_selected_circle: self.has_selected_circle,
+            rng: self.rng.clone(),
+            next_circle_id: self.next_circle_id,
+            next_cluster_id: self.next_cluster_id,
+        };
+    }
+
+    pub fn restore_snapshot(&mut self, snapshot: &StateSnapshot) {
+        self.global_time = snapshot.global_time;
+        self.circles = snapshot.circles.clone();
+        self.clusters = snapshot.clusters.clone();
+        self.selected_circle_index = snapshot.selected_circle_index;
+        self.has_selected_circle = snapshot.has_selected_circle;
+        self.rng = snapshot.rng.clone();
+        self.next_circle_id = snapshot.next_circle_id;
+        self.next_cluster_id = snapshot.next_cluster_id;
+
+        // the circles jumped, which must not count as passing any sensor
+        for sensor in self.sensors.iter_mut() {
+            sensor.track(&self.circles);
+        }
+
+        self.record_trails();
+    }
+
+    // the image file when there is one, a single colour when every pixel is the same, the whole bitmap otherwise
+    pub fn saved_background(&self) -> SavedBackground {
+        if let Some(image) = &self.background_image {
+            return SavedBackground::Image(image.clone());
+        }
+
+        let is_uniform: bool = self.background.chunks(3).all(|pixel| pixel == &self.background[0..3]);
+
+        if is_uniform && self.background.len() >= 3 {
+            return SavedBackground::Uniform(RGBColor {
+                r: self.background[0],
+                g: self.background[1],
+                b: self.background[2],
+            });
+        }
+
+        return SavedBackground::Pixels(self.background.clone());
+    }
+
+    pub fn to_scenario(&self) -> Scenario {
+        let background: SavedBackground = self.saved_background();
+
+        return Scenario {
+            version: SCENARIO_VERSION,
+            width: self.width,
+            height: self.height,
+            background,
+            settings: Settings {
+                sticky_collisions: self.sticky_collisions,
+                random_walk_step: self.random_walk_step,
+                gravity: self.gravity,
+                fluid_regions: self.fluid_regions.clone(),
+                sph: self.sph,
+                render_fluid_as_surface: self.render_fluid_as_surface,
+                flow_field: self.flow_field.clone(),
+                flow_drag: self.flow_drag,
+                show_flow_field: self.show_flow_field,
+                circle_labels: self.circle_labels,
+                trails: self.trail_settings,
+                heatmap: self.heatmap_settings,
+                thermostat: self.thermostat,
+                target_temperature: self.target_temperature,
+            },
+            seed: self.seed,
+            rng_word_position: self.rng.get_word_pos() as u64,
+            global_time: self.global_time,
+            next_circle_id: self.next_circle_id,
+            next_cluster_id: self.next_cluster_id,
+            circles: self.circles.clone(),
+            clusters: self.clusters.clone(),
+            sensors: self.sensors.iter().map(|sensor| SavedSensor {
+                name: sensor.name.clone(),
+                shape: sensor.shape,
+            }).collect(),
+        };
+    }
+
+    // replaces the whole world, collision observers stay registered; on error the world is left as it was
+    pub fn apply_scenario(&mut self, scenario: Scenario) -> Result<(), SimulationError> {
+        // the world is an image, so its size is a whole number of pixels
+        for size in [scenario.width, scenario.height] {
+            if !(1.0..=MAX_WORLD_SIZE).contains(&size) || size.fract() != 0.0 {
+                return Err(SimulationError::Scenario(format!(
+                    "Scenario world size {} x {} should be whole numbers from 1 to {}",
+                    scenario.width, scenario.height, MAX_WORLD_SIZE,
+                )));
+            }
+        }
+
+        State::check_scenario_bodies(&scenario)?;
+        State::check_heatmap_cell_size(scenario.settings.heatmap.cell_size)?;
+
+        let number_of_pixels: usize = scenario.width as usize * scenario.height as usize;
+
+        let mut background_image: Option<BackgroundImage> = None;
+
+        let background: Vec<u8> = match scenario.background {
+            SavedBackground::Uniform(color) => [color.r, color.g, color.b].repeat(number_of_pixels),
+            SavedBackground::Pixels(data) => {
+                if data.len() != number_of_pixels * 3 {
+                    return Err(SimulationError::Scenario(format!("Scenario background has {} values, expected {}", data.len(), number_of_pixels * 3)));
+                }
+
+                data
+            }
+            SavedBackground::Image(image) => {
+                let data: Vec<u8> = image.load(scenario.width as usize, scenario.height as usize)?;
+                background_image = Some(image);
+
+                data
+            }
+        };
+
+        let settings: Settings = scenario.settings;
+
+        self.width = scenario.width;
+        self.height = scenario.height;
+        self.background = background;
+        self.background_image = background_image;
+        self.circles = scenario.circles;
+        self.clusters = scenario.clusters;
+        self.has_selected_circle = false;
+        self.global_time = scenario.global_time;
+        self.sticky_collisions = settings.sticky_collisions;
+        self.random_walk_step = settings.random_walk_step;
+        self.gravity = settings.gravity;
+        self.fluid_regions = settings.fluid_regions;
+        self.sph = settings.sph;
+        self.render_fluid_as_surface = settings.render_fluid_as_surface;
+        self.flow_field = settings.flow_field;
+        self.flow_drag = settings.flow_drag;
+        self.show_flow_field = settings.show_flow_field;
+        self.circle_labels = settings.circle_labels;
+        self.trail_settings = settings.trails;
+        self.trails.clear();
+        self.heatmap_settings = HeatmapSettings {
+            decay: settings.heatmap.decay.clamp(0.0, 1.0),
+            ..settings.heatmap
+        };
+        self.heatmap = Heatmap::new(self.width, self.height, self.heatmap_settings.cell_size);
+        self.thermostat = settings.thermostat;
+        self.target_temperature = settings.target_temperature;
+        self.seed = scenario.seed;
+        self.rng = ChaCha8Rng::seed_from_u64(scenario.seed);
+        self.rng.set_word_pos(scenario.rng_word_position as u128);
+        self.next_circle_id = scenario.next_circle_id;
+        self.next_cluster_id = scenario.next_cluster_id;
+        self.collision_events.clear();
+        self.sensor_events.clear();
+        self.remove_sensors();
+        self.next_sensor_id = 0;
+
+        for saved_sensor in scenario.sensors {
+            self.add_sensor(saved_sensor.shape);
+
+            if let Some(sensor) = self.sensors.last_mut() {
+                sensor.name = saved_sensor.name;
+            }
+        }
+
+        // positions of clustered circles always follow their cluster
+        self.sync_cluster_circles();
+
+        return Ok(());
+    }
+
+    // a hand-edited file must not leave circles and clusters pointing at each other wrongly, or sizes that divide by zero
+    fn check_scenario_bodies(scenario: &Scenario) -> Result<(), SimulationError> {
+        let mut circle_ids: HashMap<usize, Option<usize>> = HashMap::new(); // circle id to its cluster id
+
+        for circle in scenario.circles.iter() {
+            if circle.id >= scenario.next_circle_id {
+                return Err(SimulationError::Scenario(format!("Circle id {} is not below next_circle_id {}", circle.id, scenario.next_circle_id)));
+            }
+
+            if circle_ids.insert(circle.id, circle.cluster_id).is_some() {
+                return Err(SimulationError::Scenario(format!("Circle id {} is used more than once", circle.id)));
+            }
+
+            if !circle.radius.is_finite() || circle.radius <= 0.0 || !circle.mass.is_finite() || circle.mass <= 0.0 {
+                return Err(SimulationError::Scenario(format!("Circle {} should have a positive radius and mass", circle.id)));
+            }
+
+            if ![circle.x_pos, circle.y_pos, circle.x_vel, circle.y_vel].iter().all(|value| value.is_finite()) {
+                return Err(SimulationError::Scenario(format!("Circle {} has a position or velocity that is not a number", circle.id)));
+            }
+
+            if let Some(cluster_id) = circle.cluster_id {
+                match scenario.clusters.iter().find(|cluster| cluster.id == cluster_id) {
+                    None => return Err(SimulationError::Scenario(format!("Circle {} belongs to cluster {}, which does not exist", circle.id, cluster_id))),
+                    Some(cluster) if !cluster.members.iter().any(|member| member.circle_id == circle.id) => {
+                        return Err(SimulationError::Scenario(format!("Circle {} belongs to cluster {}, which does not list it", circle.id, cluster_id)));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        for (index, cluster) in scenario.clusters.iter().enumerate() {
+            if cluster.id >= scenario.next_cluster_id {
+                return Err(SimulationError::Scenario(format!("Cluster id {} is not below next_cluster_id {}", cluster.id, scenario.next_cluster_id)));
+            }
+
+            if scenario.clusters[..index].iter().any(|other| other.id == cluster.id) {
+                return Err(SimulationError::Scenario(format!("Cluster id {} is used more than once", cluster.id)));
+            }
+
+            for (member_index, member) in cluster.members.iter().enumerate() {
+                if circle_ids.get(&member.circle_id) != Some(&Some(cluster.id)) {
+                    return Err(SimulationError::Scenario(format!("Cluster {} lists circle {}, which is not one of its circles", cluster.id, member.circle_id)));
+                }
+
+                if cluster.members[..member_index].iter().any(|other| other.circle_id == member.circle_id) {
+                    return Err(SimulationError::Scenario(format!("Cluster {} lists circle {} more than once", cluster.id, member.circle_id)));
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    // empties the world and starts the random sequence over, simulation settings are kept
+    pub fn restart_with_seed(&mut self, seed: u64) {
+        self.remove_all_circles();
+        self.global_time = 0;
+        self.seed = seed;
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.next_circle_id = 0;
+        self.next_cluster_id = 0;
+        self.collision_events.clear();
+        self.sensor_events.clear();
+        self.reset_sensor_counters();
+        self.heatmap.reset();
+    }
+
+    pub fn remove_all_circles(&mut self) {
+        self.circles = Vec::new();
+        self.clusters = Vec::new();
+        self.has_selected_circle = false;
+        self.trails.clear();
+    }
+
+    // drags the selected circle to the given position; clustered circles drag their whole cluster along
+    pub fn accelerate_selected_to_position(&mut self, x: f64, y: f64) {
+        if !self.has_selected_circle {
+            return;
+        }
+
+        let index: usize = self.selected_circle_index;
+
+        match self.body_of(index) {
+            Body::Free(_) => {
+                self.circles[index].accelerate_to_position(x, y);
+            }
+            Body::Clustered(cluster_index) => {
+                let delta_x: f64 = x - self.circles[index].x_pos;
+                let delta_y: f64 = y - self.circles[index].y_pos;
+                let cluster: &mut Cluster = &mut self.clusters[cluster_index];
+
+                cluster.translate(delta_x, delta_y);
+
+                if !cluster.is_fixed {
+                    cluster.x_vel = delta_x;
+                    cluster.y_vel = delta_y;
+                    cluster.angular_vel = 0.0;
+                }
+
+                self.sync_cluster_circles();
+            }
         }
     }
 
+    pub fn add_fluid_region(&mut self, region: FluidRegion) {
+        self.fluid_regions.push(region);
+    }
+
+    pub fn remove_fluid_regions(&mut self) {
+        self.fluid_regions = Vec::new();
+    }
+
+    pub fn replace_background(&mut self, new_background: Vec<u8>) -> Result<(), SimulationError> {
+        if new_background.len() != self.background.len() {
+            return Err(SimulationError::BackgroundSize {
+                expected: self.background.len(),
+                actual: new_background.len(),
+            });
+        }
+
+        self.background = new_background;
+        self.background_image = None;
+
+        return Ok(());
+    }
+
+    pub fn load_background_image(&mut self, image: BackgroundImage) -> Result<(), SimulationError> {
+        self.background = image.load(self.width as usize, self.height as usize)?;
+        self.background_image = Some(image);
+
+        return Ok(());
+    }
+
+    pub fn background_pixels(&self) -> &[u8] {
+        return &self.background;
+    }
+
     pub fn progress_one_step(&mut self) {
         self.global_time += 1;
 
+        if self.random_walk_step > 0.0 {
+            self.apply_random_walk();
+        }
+
+        self.apply_gravity();
+        self.apply_fluid_forces();
+        self.apply_sph_forces();
+        self.apply_flow_drag();
+
         for i in 0..self.circles.len() {
-            self.circles[i].move_circle(
-                0.0,
-                self.width,
-                0.0,
-                self.height,
-            );
+            if self.circles[i].cluster_id.is_none() {
+                self.circles[i].move_circle(
+                    0.0,
+                    self.width,
+                    0.0,
+                    self.height,
+                );
+            }
+        }
+
+        for i in 0..self.clusters.len() {
+            self.clusters[i].advance();
+            self.clusters[i].bounce_off_walls(0.0, self.width, 0.0, self.height);
         }
 
-        self.enumerate_collided_pairs();
+        self.sync_cluster_circles();
 
-        let mut cloned_circles_array: Vec<Circle> = Vec::with_capacity(self.circles.len());
+        let collided_pairs_list: Vec<Pair> = self.enumerate_collided_pairs();
+
+        for pair in collided_pairs_list.iter() {
+            if self.sticky_collisions {
+                self.stick_circles(pair.i, pair.j);
+            } else if self.needs_impulse_collision(pair.i, pair.j) {
+                self.collide_bodies(pair.i, pair.j);
+            }
+        }
+
+        self.sync_cluster_circles();
+
+        if !self.sticky_collisions {
+            let mut cloned_circles_array: Vec<Circle> = Vec::with_capacity(self.circles.len());
+
+            for i in 0..self.circles.len() {
+                cloned_circles_array.push(self.circles[i].clone());
+            }
+
+            let mut events: Vec<CollisionEvent> = Vec::new();
+
+            for i in 0..self.circles.len() {
+                if self.circles[i].cluster_id.is_none() && !self.circles[i].is_fluid {
+                    events.extend(self.circles[i].collide_with_other_circles(&cloned_circles_array, i));
+                }
+            }
+
+            for event in events {
+                self.emit_collision(event);
+            }
+        }
+
+        self.thermostat.apply(&mut self.circles, self.target_temperature, &mut self.rng);
+
+        for sensor in self.sensors.iter_mut() {
+            let events: Vec<SensorEvent> = sensor.update(&self.circles, self.global_time);
+            self.sensor_events.extend(events);
+        }
+
+        self.record_trails();
+
+        if self.heatmap_settings.enabled {
+            self.heatmap.accumulate(&self.circles, self.heatmap_settings.decay, self.global_time);
+        }
+    }
+
+    // starts the heatmap over on a grid of the given cell size
+    pub fn set_heatmap_cell_size(&mut self, cell_size: f64) -> Result<(), SimulationError> {
+        State::check_heatmap_cell_size(cell_size)?;
+
+        self.heatmap_settings.cell_size = cell_size;
+        self.heatmap = Heatmap::new(self.width, self.height, cell_size);
+
+        return Ok(());
+    }
+
+    // smaller cells would make a grid with more cells than pixels, or none at all
+    fn check_heatmap_cell_size(cell_size: f64) -> Result<(), SimulationError> {
+        if !cell_size.is_finite() || cell_size < 1.0 {
+            return Err(SimulationError::HeatmapCellSize(cell_size));
+        }
+
+        return Ok(());
+    }
+
+    // nothing is remembered while trails are hidden, so that showing them starts from the current positions
+    fn record_trails(&mut self) {
+        if self.trail_settings.enabled {
+            self.trails.record(&self.circles, self.global_time, self.trail_settings.length);
+        } else {
+            self.trails.clear();
+        }
+    }
+
+    // returns the id of the new sensor
+    pub fn add_sensor(&mut self, shape: SensorShape) -> usize {
+        let mut sensor: Sensor = Sensor::new(self.next_sensor_id, shape, SENSOR_COLOR);
+        self.next_sensor_id += 1;
+
+        // circles already inside do not count as entering
+        sensor.track(&self.circles);
+        self.sensors.push(sensor);
+
+        return self.next_sensor_id - 1;
+    }
+
+    pub fn remove_sensors(&mut self) {
+        self.sensors = Vec::new();
+        self.sensor_events.clear();
+    }
+
+    pub fn reset_sensor_counters(&mut self) {
+        for sensor in self.sensors.iter_mut() {
+            sensor.reset_counters();
+        }
+    }
+
+    pub fn take_sensor_events(&mut self) -> Vec<SensorEvent> {
+        return std::mem::take(&mut self.sensor_events);
+    }
+
+    pub fn add_collision_observer(&mut self, observer: Box<dyn CollisionObserver>) {
+        self.collision_observers.push(observer);
+    }
+
+    // hands over every collision recorded since the last call
+    pub fn take_collision_events(&mut self) -> Vec<CollisionEvent> {
+        return std::mem::take(&mut self.collision_events);
+    }
+
+    fn emit_collision(&mut self, mut event: CollisionEvent) {
+        event.global_time = self.global_time;
+        event = event.ordered();
+
+        for observer in self.collision_observers.iter_mut() {
+            observer.on_collision(&event);
+        }
+
+        self.collision_events.push(event);
+    }
+
+    fn apply_random_walk(&mut self) {
+        let mut direction: f64;
+
+        for i in 0..self.circles.len() {
+            if self.circles[i].cluster_id.is_none() {
+                direction = self.rng.gen_range(0.0..std::f64::consts::TAU);
+
+                self.circles[i].x_vel = self.random_walk_step * direction.cos();
+                self.circles[i].y_vel = self.random_walk_step * direction.sin();
+            }
+        }
+    }
+
+    fn apply_gravity(&mut self) {
+        if self.gravity == 0.0 {
+            return;
+        }
 
         for i in 0..self.circles.len() {
-            cloned_circles_array.push(self.circles[i].clone());
+            if self.circles[i].cluster_id.is_none() {
+                self.circles[i].y_vel += self.gravity;
+            }
+        }
+
+        for i in 0..self.clusters.len() {
+            if !self.clusters[i].is_fixed {
+                self.clusters[i].y_vel += self.gravity;
+            }
         }
+    }
+
+    // buoyancy proportional to submerged area, applied at the centre of buoyancy, plus linear viscous drag
+    fn apply_fluid_forces(&mut self) {
+        let mut area: f64;
+        let mut centre_x: f64;
+        let mut centre_y: f64;
+        let mut drag_factor: f64;
 
         for i in 0..self.circles.len() {
-            self.circles[i].collide_with_other_circles(&cloned_circles_array, i);
+            for k in 0..self.fluid_regions.len() {
+                let circle: &Circle = &self.circles[i];
+                let region: &FluidRegion = &self.fluid_regions[k];
+
+                (area, centre_x, centre_y) = region.submerged_area(circle.x_pos, circle.y_pos, circle.radius);
+
+                if area > 0.0 {
+                    let buoyancy: f64 = region.density * area * self.gravity;
+
+                    // drag grows with the submerged part of the cross-section, capped so it can only stop the circle
+                    drag_factor = region.viscosity * 2.0 * circle.radius * area / (std::f64::consts::PI * circle.radius * circle.radius) / circle.mass;
+                    drag_factor = f64::min(drag_factor, 1.0);
+
+                    let impulse_x: f64 = -drag_factor * circle.mass * circle.x_vel;
+                    let impulse_y: f64 = -drag_factor * circle.mass * circle.y_vel - buoyancy;
+
+                    let body: Body = self.body_of(i);
+                    self.apply_impulse_to_body(body, centre_x, centre_y, impulse_x, impulse_y);
+                }
+            }
         }
+
+        self.sync_cluster_circles();
     }
 
-    fn enumerate_collided_pairs(&self) {
-        struct Pair {
-            i: usize,
-            j: usize,
+    // drag towards the local flow velocity, proportional to relative velocity and cross-section
+    fn apply_flow_drag(&mut self) {
+        let flow_field: &FlowField = match self.flow_field.as_ref() {
+            Some(flow_field) => flow_field,
+            None => return,
+        };
+
+        // worked out first while the field is borrowed, applying them needs the whole state
+        let mut impulses: Vec<(usize, f64, f64, f64, f64)> = Vec::with_capacity(self.circles.len());
+
+        for (i, circle) in self.circles.iter().enumerate() {
+            let (flow_x_vel, flow_y_vel) = flow_field.velocity_at(circle.x_pos, circle.y_pos);
+
+            // capped so that drag can at most bring the circle to the flow velocity
+            let drag_factor: f64 = f64::min(self.flow_drag * 2.0 * circle.radius / circle.mass, 1.0);
+
+            let impulse_x: f64 = drag_factor * circle.mass * (flow_x_vel - circle.x_vel);
+            let impulse_y: f64 = drag_factor * circle.mass * (flow_y_vel - circle.y_vel);
+
+            impulses.push((i, circle.x_pos, circle.y_pos, impulse_x, impulse_y));
+        }
+
+        for (i, x, y, impulse_x, impulse_y) in impulses {
+            let body: Body = self.body_of(i);
+            self.apply_impulse_to_body(body, x, y, impulse_x, impulse_y);
         }
-        // check for collision
+
+        self.sync_cluster_circles();
+    }
+
+    fn apply_sph_forces(&mut self) {
+        let fluid_indices: Vec<usize> = (0..self.circles.len())
+            .filter(|&i| self.circles[i].is_fluid && self.circles[i].cluster_id.is_none())
+            .collect();
+
+        if fluid_indices.is_empty() {
+            return;
+        }
+
+        let mut grid: UniformGrid = UniformGrid::new(self.width, self.height, self.sph.smoothing_length);
+
+        for &i in fluid_indices.iter() {
+            grid.insert(i, self.circles[i].x_pos, self.circles[i].y_pos);
+        }
+
+        let accelerations: Vec<(f64, f64)> = self.sph.compute_accelerations(&self.circles, &fluid_indices, &grid);
+
+        for (k, &i) in fluid_indices.iter().enumerate() {
+            self.circles[i].x_vel += accelerations[k].0;
+            self.circles[i].y_vel += accelerations[k].1;
+        }
+    }
+
+    fn body_of(&self, circle_index: usize) -> Body {
+        match self.circles[circle_index].cluster_id {
+            Some(cluster_id) => {
+                let cluster_index: usize = self.clusters
+                    .iter()
+                    .position(|cluster| cluster.id == cluster_id)
+                    .expect("circle refers to a cluster that does not exist");
+
+                return Body::Clustered(cluster_index);
+            }
+            None => return Body::Free(circle_index),
+        }
+    }
+
+    fn body_inverse_mass(&self, body: Body) -> f64 {
+        match body {
+            Body::Free(index) => return 1.0 / self.circles[index].mass,
+            Body::Clustered(index) => return self.clusters[index].inverse_mass(),
+        }
+    }
+
+    fn body_velocity_at(&self, body: Body, x: f64, y: f64) -> (f64, f64) {
+        match body {
+            Body::Free(index) => return (self.circles[index].x_vel, self.circles[index].y_vel),
+            Body::Clustered(index) => return self.clusters[index].velocity_at(x, y),
+        }
+    }
+
+    // rotational part of the effective inverse mass for an impulse along (n_x, n_y) applied at (x, y)
+    fn body_angular_term(&self, body: Body, x: f64, y: f64, n_x: f64, n_y: f64) -> f64 {
+        match body {
+            // impulses on a single circle always go through its centre
+            Body::Free(_) => return 0.0,
+            Body::Clustered(index) => {
+                let cluster: &Cluster = &self.clusters[index];
+                let r_cross_n: f64 = (x - cluster.x_pos) * n_y - (y - cluster.y_pos) * n_x;
+
+                return r_cross_n * r_cross_n * cluster.inverse_moment_of_inertia();
+            }
+        }
+    }
+
+    fn apply_impulse_to_body(&mut self, body: Body, x: f64, y: f64, impulse_x: f64, impulse_y: f64) {
+        match body {
+            Body::Free(index) => {
+                self.circles[index].x_vel += impulse_x / self.circles[index].mass;
+                self.circles[index].y_vel += impulse_y / self.circles[index].mass;
+            }
+            Body::Clustered(index) => self.clusters[index].apply_impulse(x, y, impulse_x, impulse_y),
+        }
+    }
+
+    fn translate_body(&mut self, body: Body, delta_x: f64, delta_y: f64) {
+        match body {
+            Body::Free(index) => {
+                self.circles[index].x_pos += delta_x;
+                self.circles[index].y_pos += delta_y;
+            }
+            Body::Clustered(index) => self.clusters[index].translate(delta_x, delta_y),
+        }
+    }
+
+    // pushes two overlapping bodies apart along the line through the circle centres, lighter body moves more
+    fn separate_bodies(&mut self, i: usize, j: usize, body_a: Body, body_b: Body) {
+        let rel_x: f64 = self.circles[j].x_pos - self.circles[i].x_pos;
+        let rel_y: f64 = self.circles[j].y_pos - self.circles[i].y_pos;
+        let distance: f64 = f64::sqrt(rel_x * rel_x + rel_y * rel_y);
+        let overlap: f64 = self.circles[i].radius + self.circles[j].radius - distance;
+
+        let inverse_mass_a: f64 = self.body_inverse_mass(body_a);
+        let inverse_mass_b: f64 = self.body_inverse_mass(body_b);
+
+        if overlap <= 0.0 || distance == 0.0 || inverse_mass_a + inverse_mass_b == 0.0 {
+            return;
+        }
+
+        let n_x: f64 = rel_x / distance;
+        let n_y: f64 = rel_y / distance;
+        let share_a: f64 = overlap * inverse_mass_a / (inverse_mass_a + inverse_mass_b);
+        let share_b: f64 = overlap * inverse_mass_b / (inverse_mass_a + inverse_mass_b);
+
+        self.translate_body(body_a, -n_x * share_a, -n_y * share_a);
+        self.translate_body(body_b, n_x * share_b, n_y * share_b);
+        self.sync_cluster_circles();
+    }
+
+    // merges the bodies of two touching circles into one rigid cluster, conserving mass, momentum and angular momentum
+    fn stick_circles(&mut self, i: usize, j: usize) {
+        let body_a: Body = self.body_of(i);
+        let body_b: Body = self.body_of(j);
+
+        if body_a == body_b {
+            return;
+        }
+
+        let event: CollisionEvent = self.sticking_event(i, j, body_a, body_b);
+
+        self.separate_bodies(i, j, body_a, body_b);
+
+        let mut members: Vec<(usize, f64, f64, f64, f64)> = Vec::new();
+        let mut momentum_x: f64 = 0.0;
+        let mut momentum_y: f64 = 0.0;
+        let mut angular_momentum: f64 = 0.0;
+        let mut is_fixed: bool = false;
+        let mut old_cluster_ids: Vec<usize> = Vec::new();
+
+        for body in [body_a, body_b] {
+            match body {
+                Body::Free(index) => {
+                    let circle: &Circle = &self.circles[index];
+
+                    members.push((circle.id, circle.x_pos, circle.y_pos, circle.radius, circle.mass));
+                    momentum_x += circle.mass * circle.x_vel;
+                    momentum_y += circle.mass * circle.y_vel;
+                    angular_momentum += circle.mass * (circle.x_pos * circle.y_vel - circle.y_pos * circle.x_vel);
+                }
+                Body::Clustered(index) => {
+                    let cluster: &Cluster = &self.clusters[index];
+                    let (p_x, p_y) = cluster.linear_momentum();
+
+                    for member in cluster.members.iter() {
+                        let (x, y) = cluster.member_position(member);
+                        members.push((member.circle_id, x, y, member.radius, member.mass));
+                    }
+
+                    momentum_x += p_x;
+                    momentum_y += p_y;
+                    angular_momentum += cluster.angular_momentum();
+                    is_fixed = is_fixed || cluster.is_fixed;
+                    old_cluster_ids.push(cluster.id);
+                }
+            }
+        }
+
+        self.clusters.retain(|cluster| !old_cluster_ids.contains(&cluster.id));
+
+        let new_cluster: Cluster = Cluster::from_members(
+            self.next_cluster_id,
+            &members,
+            momentum_x,
+            momentum_y,
+            angular_momentum,
+            is_fixed,
+        );
+        self.next_cluster_id += 1;
+
+        for circle in self.circles.iter_mut() {
+            if new_cluster.contains(circle.id) {
+                circle.cluster_id = Some(new_cluster.id);
+            }
+        }
+
+        self.clusters.push(new_cluster);
+        self.sync_cluster_circles();
+        self.emit_collision(event);
+    }
+
+    // describes a perfectly inelastic contact, the impulse is what it takes to stop the approach along the normal
+    fn sticking_event(&self, i: usize, j: usize, body_a: Body, body_b: Body) -> CollisionEvent {
+        let rel_x: f64 = self.circles[j].x_pos - self.circles[i].x_pos;
+        let rel_y: f64 = self.circles[j].y_pos - self.circles[i].y_pos;
+        let distance: f64 = f64::sqrt(rel_x * rel_x + rel_y * rel_y);
+
+        let (n_x, n_y) = if distance > 0.0 {(rel_x / distance, rel_y / distance)} else {(1.0, 0.0)};
+        let contact_x: f64 = self.circles[i].x_pos + n_x * self.circles[i].radius;
+        let contact_y: f64 = self.circles[i].y_pos + n_y * self.circles[i].radius;
+
+        let (v_a_x, v_a_y) = self.body_velocity_at(body_a, contact_x, contact_y);
+        let (v_b_x, v_b_y) = self.body_velocity_at(body_b, contact_x, contact_y);
+        let v_n: f64 = (v_b_x - v_a_x) * n_x + (v_b_y - v_a_y) * n_y;
+
+        let effective_inverse_mass: f64 =
+            self.body_inverse_mass(body_a) + self.body_inverse_mass(body_b) +
+            self.body_angular_term(body_a, contact_x, contact_y, n_x, n_y) +
+            self.body_angular_term(body_b, contact_x, contact_y, n_x, n_y);
+
+        let impulse: f64 = if effective_inverse_mass > 0.0 {f64::abs(v_n) / effective_inverse_mass} else {0.0};
+
+        return CollisionEvent {
+            global_time: self.global_time,
+            circle_a: self.circles[i].id,
+            circle_b: self.circles[j].id,
+            contact_x,
+            contact_y,
+            normal_x: n_x,
+            normal_y: n_y,
+            relative_speed: -v_n,
+            impulse,
+            time_in_step: 1.0,
+            is_sticky: true,
+        };
+    }
+
+    // clusters and fluid particles are collided with rigid body impulses, plain circles use Circle::collide_with_other_circles
+    fn needs_impulse_collision(&self, i: usize, j: usize) -> bool {
+        return self.circles[i].cluster_id.is_some() || self.circles[j].cluster_id.is_some()
+            || self.circles[i].is_fluid || self.circles[j].is_fluid;
+    }
+
+    // elastic collision between the bodies of two touching circles
+    fn collide_bodies(&mut self, i: usize, j: usize) {
+        let body_a: Body = self.body_of(i);
+        let body_b: Body = self.body_of(j);
+
+        if body_a == body_b {
+            return;
+        }
+
+        let rel_x: f64 = self.circles[j].x_pos - self.circles[i].x_pos;
+        let rel_y: f64 = self.circles[j].y_pos - self.circles[i].y_pos;
+        let distance: f64 = f64::sqrt(rel_x * rel_x + rel_y * rel_y);
+
+        if distance == 0.0 {
+            return;
+        }
+
+        let n_x: f64 = rel_x / distance;
+        let n_y: f64 = rel_y / distance;
+        let contact_x: f64 = self.circles[i].x_pos + n_x * self.circles[i].radius;
+        let contact_y: f64 = self.circles[i].y_pos + n_y * self.circles[i].radius;
+
+        self.separate_bodies(i, j, body_a, body_b);
+
+        let (v_a_x, v_a_y) = self.body_velocity_at(body_a, contact_x, contact_y);
+        let (v_b_x, v_b_y) = self.body_velocity_at(body_b, contact_x, contact_y);
+        let v_n: f64 = (v_b_x - v_a_x) * n_x + (v_b_y - v_a_y) * n_y;
+
+        // bodies are already moving apart
+        if v_n >= 0.0 {
+            return;
+        }
+
+        let effective_inverse_mass: f64 =
+            self.body_inverse_mass(body_a) + self.body_inverse_mass(body_b) +
+            self.body_angular_term(body_a, contact_x, contact_y, n_x, n_y) +
+            self.body_angular_term(body_b, contact_x, contact_y, n_x, n_y);
+
+        if effective_inverse_mass == 0.0 {
+            return;
+        }
+
+        let impulse: f64 = -2.0 * v_n / effective_inverse_mass;
+
+        self.apply_impulse_to_body(body_a, contact_x, contact_y, -impulse * n_x, -impulse * n_y);
+        self.apply_impulse_to_body(body_b, contact_x, contact_y, impulse * n_x, impulse * n_y);
+
+        self.emit_collision(CollisionEvent {
+            global_time: self.global_time,
+            circle_a: self.circles[i].id,
+            circle_b: self.circles[j].id,
+            contact_x,
+            contact_y,
+            normal_x: n_x,
+            normal_y: n_y,
+            relative_speed: -v_n,
+            impulse,
+            time_in_step: 1.0,
+            is_sticky: false,
+        });
+    }
+
+    // copies positions and velocities of every cluster onto its member circles
+    fn sync_cluster_circles(&mut self) {
+        if self.clusters.is_empty() {
+            return;
+        }
+
+        let mut index_by_id: HashMap<usize, usize> = HashMap::with_capacity(self.circles.len());
+
+        for (index, circle) in self.circles.iter().enumerate() {
+            index_by_id.insert(circle.id, index);
+        }
+
+        for cluster in self.clusters.iter() {
+            for member in cluster.members.iter() {
+                if let Some(&index) = index_by_id.get(&member.circle_id) {
+                    let (x, y) = cluster.member_position(member);
+                    let (x_vel, y_vel) = cluster.velocity_at(x, y);
+
+                    self.circles[index].x_pos = x;
+                    self.circles[index].y_pos = y;
+                    self.circles[index].x_vel = x_vel;
+                    self.circles[index].y_vel = y_vel;
+                }
+            }
+        }
+    }
+
+    // wide enough that touching circles are always in the same or neighbouring cells
+    pub fn broadphase_cell_size(&self) -> f64 {
+        let mut max_radius: f64 = 1.0;
+
+        for circle in self.circles.iter() {
+            max_radius = f64::max(max_radius, circle.radius);
+        }
+
+        return 2.0 * max_radius;
+    }
+
+    fn enumerate_collided_pairs(&self) -> Vec<Pair> {
+        // check for collision, only circles from nearby grid cells are compared
         let mut distance_squared: f64;
         let mut sum_radii_squared: f64;
         let mut collided_pairs_list: Vec<Pair> = Vec::new();
 
+        let cell_size: f64 = self.broadphase_cell_size();
+        let max_radius: f64 = cell_size / 2.0;
+
+        let mut grid: UniformGrid = UniformGrid::new(self.width, self.height, cell_size);
+
+        for (index, circle) in self.circles.iter().enumerate() {
+            grid.insert(index, circle.x_pos, circle.y_pos);
+        }
+
         for j in 0..self.circles.len() {
-            for i in j..self.circles.len() {
-                if i != j {
+            let mut neighbours: Vec<usize> = grid.query(self.circles[j].x_pos, self.circles[j].y_pos, self.circles[j].radius + max_radius);
+            neighbours.sort_unstable();
+
+            for i in neighbours {
+                let is_same_cluster: bool =
+                    self.circles[i].cluster_id.is_some() && self.circles[i].cluster_id == self.circles[j].cluster_id;
+                // fluid particles push each other through SPH pressure
+                let is_fluid_pair: bool = self.circles[i].is_fluid && self.circles[j].is_fluid;
+
+                if i > j && !is_same_cluster && !is_fluid_pair {
                     distance_squared = 
                     (self.circles[i].x_pos - self.circles[j].x_pos) * (self.circles[i].x_pos - self.circles[j].x_pos) +
                     (self.circles[i].y_pos - self.circles[j].y_pos) * (self.circles[i].y_pos - self.circles[j].y_pos);
@@ -193,13 +1392,50 @@ impl State {
             }
         }
 
-        if collided_pairs_list.len() > 0 {
-            print!("{:>5}: ", self.global_time);
-            for i in 0..collided_pairs_list.len() {
-                print!("{:>3} <->{:>3};", collided_pairs_list[i].i, collided_pairs_list[i].j);
-            };
-            println!("");
+        return collided_pairs_list;
+    }
+
+    // FNV-1a hash of everything that moves, equal checksums mean (almost certainly) equal simulations
+    pub fn checksum(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+
+        let mut add = |value: u64| {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        };
+
+        add(self.global_time as u64);
+        add(self.rng.get_word_pos() as u64);
+
+        for circle in self.circles.iter() {
+            add(circle.id as u64);
+            add(circle.x_pos.to_bits());
+            add(circle.y_pos.to_bits());
+            add(circle.x_vel.to_bits());
+            add(circle.y_vel.to_bits());
+        }
+
+        for cluster in self.clusters.iter() {
+            add(cluster.id as u64);
+            add(cluster.x_pos.to_bits());
+            add(cluster.y_pos.to_bits());
+            add(cluster.angle.to_bits());
+            add(cluster.x_vel.to_bits());
+            add(cluster.y_vel.to_bits());
+            add(cluster.angular_vel.to_bits());
         }
+
+        return hash;
+    }
+
+    // instantaneous temperature of the free circles, from their mean kinetic energy
+    pub fn get_temperature(&self) -> f64 {
+        return thermostat::measure_temperature(&self.circles);
+    }
+
+    pub fn set_maxwell_boltzmann_velocities(&mut self, temperature: f64) {
+        thermostat::set_maxwell_boltzmann_velocities(&mut self.circles, temperature, &mut self.rng);
     }
 
     pub fn get_total_momentum(&self) -> f64 {
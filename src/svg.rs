@@ -0,0 +1,222 @@
+// Vector export of the scene for print: one SVG unit is one unit of the world, whatever the window size.
+// Every kind of element goes into its own group, so that e.g. the velocity arrows can be hidden in an editor.
+
+use std::fs;
+
+use crate::{
+    common_structs::RGBColor,
+    error::SimulationError,
+    scenario::SavedBackground,
+    sensor::SensorShape,
+    state::State,
+    trails,
+};
+
+const WALL_COLOR: RGBColor = RGBColor { r: 0, g: 0, b: 0 };
+const WALL_WIDTH: f64 = 2.0;
+const BOND_COLOR: RGBColor = RGBColor { r: 90, g: 90, b: 90 };
+const VELOCITY_COLOR: RGBColor = RGBColor { r: 200, g: 30, b: 30 };
+const VELOCITY_SCALE: f64 = 10.0; // arrow length per unit of speed, as in one step drawn ten times longer
+const MIN_DRAWN_SPEED: f64 = 0.01;
+const FLUID_OPACITY: f64 = 0.35;
+
+pub fn save_svg(state: &State, path: &str) -> Result<(), SimulationError> {
+    fs::write(path, scene_to_svg(state)?)?;
+
+    return Ok(());
+}
+
+pub fn scene_to_svg(state: &State) -> Result<String, SimulationError> {
+    let mut lines: Vec<String> = Vec::new();
+
+    lines.push(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = number(state.width),
+        h = number(state.height),
+    ));
+    lines.push(format!(
+        "<defs><marker id=\"arrowhead\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto-start-reverse\"><path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"{}\"/></marker></defs>",
+        color(VELOCITY_COLOR),
+    ));
+
+    lines.push(String::from("<g id=\"background\">"));
+    match state.saved_background() {
+        SavedBackground::Uniform(background) => lines.push(format!(
+            "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            number(state.width),
+            number(state.height),
+            color(background),
+        )),
+        // an image file is embedded as fitted to the world, so that the SVG stands on its own
+        SavedBackground::Pixels(_) | SavedBackground::Image(_) => lines.push(format!(
+            "<image x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" href=\"data:image/png;base64,{}\"/>",
+            number(state.width),
+            number(state.height),
+            base64(&png_bytes(state.background_pixels(), state.width as u32, state.height as u32)?),
+        )),
+    }
+    lines.push(String::from("</g>"));
+
+    // the window fades a trail towards its oldest position, a polyline keeps the circle's opacity throughout
+    lines.push(format!("<g id=\"trails\" fill=\"none\" stroke-width=\"{}\">", trails::LINE_THICKNESS));
+    if state.trail_settings.enabled {
+        for circle in state.circles.iter().filter(|circle| circle.leaves_trail && !circle.is_fluid) {
+            let positions: Vec<(f64, f64)> = state.trails.positions(circle.id, state.trail_settings.length);
+
+            if positions.len() > 1 {
+                let points: Vec<String> = positions.iter().map(|(x, y)| format!("{},{}", number(*x), number(*y))).collect();
+
+                lines.push(format!(
+                    "<polyline points=\"{}\" stroke=\"{}\" stroke-opacity=\"{}\"/>",
+                    points.join(" "),
+                    color(circle.body_color()),
+                    number(circle.opacity),
+                ));
+            }
+        }
+    }
+    lines.push(String::from("</g>"));
+
+    lines.push(String::from("<g id=\"circles\">"));
+    for circle in state.circles.iter() {
+        lines.push(circle.to_svg());
+    }
+    lines.push(String::from("</g>"));
+
+    // clusters are held together by rigid bonds from their centre of mass to every member
+    lines.push(format!("<g id=\"constraints\" stroke=\"{}\" stroke-width=\"1\">", color(BOND_COLOR)));
+    for cluster in state.clusters.iter() {
+        for circle in state.circles.iter().filter(|circle| circle.cluster_id == Some(cluster.id)) {
+            lines.push(line(cluster.x_pos, cluster.y_pos, circle.x_pos, circle.y_pos));
+        }
+
+        lines.push(format!("<circle cx=\"{}\" cy=\"{}\" r=\"2\" fill=\"{}\"/>", number(cluster.x_pos), number(cluster.y_pos), color(BOND_COLOR)));
+    }
+    lines.push(String::from("</g>"));
+
+    lines.push(String::from("<g id=\"fluid\">"));
+    for region in state.fluid_regions.iter() {
+        lines.push(format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" fill-opacity=\"{}\"/>",
+            number(region.x_start),
+            number(region.y_start),
+            number(region.x_end - region.x_start),
+            number(region.y_end - region.y_start),
+            color(region.color),
+            FLUID_OPACITY,
+        ));
+    }
+    lines.push(String::from("</g>"));
+
+    lines.push(String::from("<g id=\"sensors\" fill=\"none\" stroke-width=\"1\">"));
+    for sensor in state.sensors.iter() {
+        lines.push(match sensor.shape {
+            SensorShape::Rectangle { x_start, x_end, y_start, y_end } => format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" stroke=\"{}\"/>",
+                number(x_start),
+                number(y_start),
+                number(x_end - x_start),
+                number(y_end - y_start),
+                color(sensor.color),
+            ),
+            SensorShape::Circle { x_pos, y_pos, radius } => format!(
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" stroke=\"{}\"/>",
+                number(x_pos),
+                number(y_pos),
+                number(radius),
+                color(sensor.color),
+            ),
+            SensorShape::Line { x_start, y_start, x_end, y_end } => format!(
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"/>",
+                number(x_start),
+                number(y_start),
+                number(x_end),
+                number(y_end),
+                color(sensor.color),
+            ),
+        });
+    }
+    lines.push(String::from("</g>"));
+
+    lines.push(format!(
+        "<g id=\"velocities\" stroke=\"{}\" stroke-width=\"1\" marker-end=\"url(#arrowhead)\">",
+        color(VELOCITY_COLOR),
+    ));
+    for circle in state.circles.iter() {
+        if circle.x_vel.hypot(circle.y_vel) > MIN_DRAWN_SPEED {
+            lines.push(line(
+                circle.x_pos,
+                circle.y_pos,
+                circle.x_pos + circle.x_vel * VELOCITY_SCALE,
+                circle.y_pos + circle.y_vel * VELOCITY_SCALE,
+            ));
+        }
+    }
+    lines.push(String::from("</g>"));
+
+    lines.push(format!(
+        "<rect id=\"walls\" x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+        number(state.width),
+        number(state.height),
+        color(WALL_COLOR),
+        WALL_WIDTH,
+    ));
+
+    lines.push(String::from("</svg>"));
+
+    return Ok(lines.join("\n") + "\n");
+}
+
+pub fn color(color: RGBColor) -> String {
+    return format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b);
+}
+
+// three decimals are far below what a printer resolves, and keep the file small
+pub fn number(value: f64) -> String {
+    let text: String = format!("{:.3}", value);
+
+    return String::from(text.trim_end_matches('0').trim_end_matches('.'));
+}
+
+fn line(x_start: f64, y_start: f64, x_end: f64, y_end: f64) -> String {
+    return format!(
+        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>",
+        number(x_start),
+        number(y_start),
+        number(x_end),
+        number(y_end),
+    );
+}
+
+fn png_bytes(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, SimulationError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder.write_header().and_then(|mut writer| writer.write_image_data(pixels))?;
+
+    return Ok(bytes);
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text: String = String::with_capacity(bytes.len() / 3 * 4 + 4);
+
+    for chunk in bytes.chunks(3) {
+        let group: u32 = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for k in 0..4 {
+            if k <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * k) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    return text;
+}
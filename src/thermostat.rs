@@ -0,0 +1,99 @@
+// Temperature of the circles treated as a 2D ideal gas, and thermostats holding it fixed
+// Boltzmann constant is taken as 1, so in 2D the mean kinetic energy per circle equals the temperature
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::circle::Circle;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Thermostat {
+    Off,
+    VelocityRescaling,
+    Berendsen {
+        time_constant: f64, // in steps
+    },
+    Andersen {
+        collision_frequency: f64, // probability per step that a circle is re-thermalised
+    },
+}
+
+// only free circles take part, clusters are rigid bodies and not gas particles
+fn is_gas_particle(circle: &Circle) -> bool {
+    return circle.cluster_id.is_none();
+}
+
+pub fn measure_temperature(circles: &Vec<Circle>) -> f64 {
+    let mut kinetic_energy: f64 = 0.0;
+    let mut number_of_particles: usize = 0;
+
+    for circle in circles.iter().filter(|circle| is_gas_particle(circle)) {
+        kinetic_energy += 0.5 * circle.mass * (circle.x_vel * circle.x_vel + circle.y_vel * circle.y_vel);
+        number_of_particles += 1;
+    }
+
+    if number_of_particles == 0 {
+        return 0.0;
+    }
+
+    return kinetic_energy / number_of_particles as f64;
+}
+
+// standard normal sample using the Box-Muller transform
+fn normal_sample<R: Rng>(rng: &mut R) -> f64 {
+    let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.gen_range(0.0..1.0);
+
+    return f64::sqrt(-2.0 * u1.ln()) * (std::f64::consts::TAU * u2).cos();
+}
+
+fn draw_maxwell_boltzmann_velocity<R: Rng>(circle: &mut Circle, temperature: f64, rng: &mut R) {
+    let sigma: f64 = f64::sqrt(temperature / circle.mass);
+
+    circle.x_vel = sigma * normal_sample(rng);
+    circle.y_vel = sigma * normal_sample(rng);
+}
+
+pub fn set_maxwell_boltzmann_velocities<R: Rng>(circles: &mut Vec<Circle>, temperature: f64, rng: &mut R) {
+    for circle in circles.iter_mut().filter(|circle| is_gas_particle(circle)) {
+        draw_maxwell_boltzmann_velocity(circle, temperature, rng);
+    }
+}
+
+impl Thermostat {
+    pub fn apply<R: Rng>(&self, circles: &mut Vec<Circle>, target_temperature: f64, rng: &mut R) {
+        match *self {
+            Thermostat::Off => {}
+            Thermostat::VelocityRescaling => {
+                let temperature: f64 = measure_temperature(circles);
+
+                if temperature > 0.0 {
+                    scale_velocities(circles, f64::sqrt(target_temperature / temperature));
+                }
+            }
+            Thermostat::Berendsen { time_constant } => {
+                let temperature: f64 = measure_temperature(circles);
+
+                if temperature > 0.0 {
+                    let factor: f64 = 1.0 + (target_temperature / temperature - 1.0) / time_constant;
+
+                    scale_velocities(circles, f64::sqrt(f64::max(factor, 0.0)));
+                }
+            }
+            Thermostat::Andersen { collision_frequency } => {
+                for circle in circles.iter_mut().filter(|circle| is_gas_particle(circle)) {
+                    if rng.gen_range(0.0..1.0) < collision_frequency {
+                        draw_maxwell_boltzmann_velocity(circle, target_temperature, rng);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn scale_velocities(circles: &mut Vec<Circle>, factor: f64) {
+    for circle in circles.iter_mut().filter(|circle| is_gas_particle(circle)) {
+        circle.x_vel *= factor;
+        circle.y_vel *= factor;
+    }
+}
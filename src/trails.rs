@@ -0,0 +1,140 @@
+// Motion trails: the last positions of every circle, drawn beneath the circles as a polyline or as dots that
+// fade towards the oldest position. Positions are remembered per step of global time, so stepping back through
+// the history shortens a trail instead of drawing it across the jump.
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circle::Circle,
+    common_structs::{BlendMode, RGBACanvas, RGBAColor},
+    drawing::{Canvas, LineStyle},
+};
+
+pub const LINE_THICKNESS: f64 = 1.5;
+const NEWEST_DOT_RADIUS: f64 = 3.0;
+const OLDEST_DOT_RADIUS: f64 = 0.5;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum TrailStyle {
+    #[default]
+    Line,
+    Dots,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TrailSettings {
+    pub enabled: bool,
+    pub length: usize, // positions kept per circle
+    pub style: TrailStyle,
+}
+
+impl Default for TrailSettings {
+    fn default() -> TrailSettings {
+        return TrailSettings {
+            enabled: false,
+            length: 60,
+            style: TrailStyle::Line,
+        };
+    }
+}
+
+struct TrailPoint {
+    global_time: usize,
+    x: f64,
+    y: f64,
+}
+
+#[derive(Default)]
+pub struct Trails {
+    points: HashMap<usize, VecDeque<TrailPoint>>, // by circle id, oldest first
+}
+
+impl Trails {
+    // call once the circles are where they are at global_time
+    pub fn record(&mut self, circles: &[Circle], global_time: usize, length: usize) {
+        self.points.retain(|id, _| circles.iter().any(|circle| circle.id == *id));
+
+        for circle in circles.iter().filter(|circle| circle.leaves_trail && !circle.is_fluid) {
+            let points: &mut VecDeque<TrailPoint> = self.points.entry(circle.id).or_default();
+
+            // positions from the future after rewinding, or from before a jump, do not belong to this trail
+            while points.back().is_some_and(|point| point.global_time >= global_time) {
+                points.pop_back();
+            }
+
+            if points.back().is_some_and(|point| point.global_time + 1 != global_time) {
+                points.clear();
+            }
+
+            points.push_back(TrailPoint {
+                global_time,
+                x: circle.x_pos,
+                y: circle.y_pos,
+            });
+
+            while points.len() > length {
+                points.pop_front();
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    // the newest positions of one circle, oldest first, at most length of them
+    pub fn positions(&self, id: usize, length: usize) -> Vec<(f64, f64)> {
+        let points: &VecDeque<TrailPoint> = match self.points.get(&id) {
+            Some(points) => points,
+            None => return Vec::new(),
+        };
+
+        return points.iter().skip(points.len().saturating_sub(length)).map(|point| (point.x, point.y)).collect();
+    }
+
+    pub fn put_on_canvas(&self, circles: &[Circle], canvas: &mut RGBACanvas, settings: &TrailSettings, scale: f64) {
+        for circle in circles.iter().filter(|circle| circle.leaves_trail && !circle.is_fluid) {
+            let points: &VecDeque<TrailPoint> = match self.points.get(&circle.id) {
+                Some(points) if points.len() > 1 => points,
+                _ => continue,
+            };
+
+            let count: usize = points.len().min(settings.length);
+            let skipped: usize = points.len() - count;
+
+            for (age, point) in points.iter().skip(skipped).enumerate() {
+                // 1.0 for the newest position, close to 0.0 for the oldest
+                let freshness: f64 = (age + 1) as f64 / count as f64;
+
+                match settings.style {
+                    TrailStyle::Line => {
+                        if age == 0 {
+                            continue;
+                        }
+
+                        let previous: &TrailPoint = &points[skipped + age - 1];
+                        let style: LineStyle = LineStyle::new(circle.body_color())
+                            .thickness(LINE_THICKNESS * scale)
+                            .opacity(freshness * circle.opacity);
+
+                        canvas.draw_line(previous.x * scale, previous.y * scale, point.x * scale, point.y * scale, &style);
+                    }
+                    TrailStyle::Dots => {
+                        let radius: f64 = OLDEST_DOT_RADIUS + (NEWEST_DOT_RADIUS - OLDEST_DOT_RADIUS) * freshness;
+                        let alpha: u8 = (freshness * circle.opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+                        canvas.composite_circle(
+                            point.x * scale,
+                            point.y * scale,
+                            radius * scale,
+                            RGBAColor::from_rgb(circle.body_color(), alpha),
+                            BlendMode::Normal,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+// Frame capture of the rendered view, for making videos without a screen recorder.
+// The format follows the file name:
+//     frames.png  numbered PNG files next to it, frames_00000.png, frames_00001.png, ...
+//     movie.gif   animated GIF that loops forever
+//     movie.y4m   raw YUV4MPEG2 stream; a named pipe (mkfifo) lets it go straight into ffmpeg
+// Frames can carry a caption in their top left corner and the simulation time in the bottom left one.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::{
+    common_structs::{RGBCanvas, RGBColor},
+    error::SimulationError,
+    export,
+    text,
+};
+
+pub const DEFAULT_FRAMES_PER_SECOND: u32 = 30;
+const GIF_QUANTISATION_SPEED: i32 = 10; // 1 is best and slowest, 30 fastest
+const CAPTION_COLOR: RGBColor = RGBColor { r: 255, g: 255, b: 255 };
+const CAPTION_SHADOW_COLOR: RGBColor = RGBColor { r: 0, g: 0, b: 0 };
+const CAPTION_MARGIN: usize = 4;
+const FRAME_HEIGHT_PER_TEXT_SCALE: usize = 400; // text grows by a font pixel per this many rows of frame
+
+#[derive(Clone)]
+pub struct VideoOptions {
+    pub path: String,
+    pub every: usize, // capture every Nth step
+    pub size: Option<(usize, usize)>, // None keeps the size of the world
+    pub frames_per_second: u32,
+    pub caption: Option<String>,
+    pub timestamp: bool, // writes the step the frame shows
+}
+
+enum FrameWriter {
+    PngSequence { stem: String },
+    Gif(gif::Encoder<BufWriter<File>>),
+    Y4m(BufWriter<File>),
+}
+
+pub struct VideoRecorder {
+    options: VideoOptions,
+    width: usize,
+    height: usize,
+    writer: FrameWriter,
+    steps_seen: usize,
+    pub frames_written: usize,
+}
+
+// "640x480" as given in dialogs and on the command line
+pub fn parse_size(text: &str) -> Result<(usize, usize), SimulationError> {
+    let error = || SimulationError::Video(format!("Frame size should look like 640x480, got {}", text));
+    let (width, height) = text.trim().split_once('x').ok_or_else(error)?;
+
+    return match (width.trim().parse::<usize>(), height.trim().parse::<usize>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(error()),
+    };
+}
+
+impl VideoRecorder {
+    pub fn start(options: VideoOptions, world_width: f64, world_height: f64) -> Result<VideoRecorder, SimulationError> {
+        if options.every == 0 {
+            return Err(SimulationError::Video(String::from("Frames should be captured at least every step, not every 0th")));
+        }
+
+        let (width, height) = options.size.unwrap_or((world_width as usize, world_height as usize));
+        let path: &str = &options.path;
+
+        let writer: FrameWriter = if let Some(stem) = path.strip_suffix(".png") {
+            FrameWriter::PngSequence { stem: String::from(stem) }
+        } else if path.ends_with(".gif") {
+            if width > u16::MAX as usize || height > u16::MAX as usize {
+                return Err(SimulationError::Video(format!("GIF frames can be at most {} pixels wide and high", u16::MAX)));
+            }
+
+            let mut encoder = gif::Encoder::new(create(path)?, width as u16, height as u16, &[])?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+
+            FrameWriter::Gif(encoder)
+        } else if path.ends_with(".y4m") {
+            let mut file: BufWriter<File> = create(path)?;
+            // C444 keeps full colour resolution, so odd frame sizes are fine
+            writeln!(file, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, options.frames_per_second)?;
+
+            FrameWriter::Y4m(file)
+        } else {
+            return Err(SimulationError::FileFormat(".png, .gif or .y4m"));
+        };
+
+        return Ok(VideoRecorder {
+            options,
+            width,
+            height,
+            writer,
+            steps_seen: 0,
+            frames_written: 0,
+        });
+    }
+
+    // called after every step, keeps one in every options.every
+    pub fn capture(&mut self, view: &RGBCanvas, global_time: usize) -> Result<(), SimulationError> {
+        self.steps_seen += 1;
+
+        if (self.steps_seen - 1) % self.options.every != 0 {
+            return Ok(());
+        }
+
+        let (width, height) = (self.width, self.height);
+        let mut frame: RGBCanvas = RGBCanvas {
+            width,
+            height,
+            data: resample(view, width, height),
+        };
+
+        // drawn after resampling, so that the text stays sharp whatever the frame size
+        self.put_captions_on_frame(&mut frame, global_time);
+
+        let pixels: &[u8] = &frame.data;
+
+        match &mut self.writer {
+            FrameWriter::PngSequence { stem } => {
+                export::save_image(&frame, &format!("{}_{:05}.png", stem, self.frames_written))?;
+            }
+            FrameWriter::Gif(encoder) => {
+                let mut frame = gif::Frame::from_rgb_speed(width as u16, height as u16, pixels, GIF_QUANTISATION_SPEED);
+                // in hundredths of a second, so anything above 100 frames per second plays at 100
+                frame.delay = (100 / self.options.frames_per_second.max(1)).max(1) as u16;
+
+                encoder.write_frame(&frame)?;
+            }
+            FrameWriter::Y4m(file) => {
+                let (luma, blue, red) = to_yuv(pixels);
+
+                file.write_all(b"FRAME\n")
+                    .and_then(|_| file.write_all(&luma))
+                    .and_then(|_| file.write_all(&blue))
+                    .and_then(|_| file.write_all(&red))?;
+            }
+        }
+
+        self.frames_written += 1;
+
+        return Ok(());
+    }
+
+    fn put_captions_on_frame(&self, frame: &mut RGBCanvas, global_time: usize) {
+        let scale: usize = 1 + frame.height / FRAME_HEIGHT_PER_TEXT_SCALE;
+        let margin: i32 = (CAPTION_MARGIN * scale) as i32;
+
+        if let Some(caption) = &self.options.caption {
+            frame.draw_text_with_shadow(margin, margin, caption, CAPTION_COLOR, CAPTION_SHADOW_COLOR, scale);
+        }
+
+        if self.options.timestamp {
+            let timestamp: String = format!("t = {}", global_time);
+            let (_, text_height) = text::text_size(&timestamp, scale);
+
+            frame.draw_text_with_shadow(
+                margin,
+                frame.height as i32 - margin - text_height as i32,
+                &timestamp,
+                CAPTION_COLOR,
+                CAPTION_SHADOW_COLOR,
+                scale,
+            );
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        return &self.options.path;
+    }
+
+    pub fn finish(self) -> Result<usize, SimulationError> {
+        match self.writer {
+            FrameWriter::PngSequence { .. } => {}
+            FrameWriter::Gif(encoder) => {
+                encoder.into_inner()?;
+            }
+            FrameWriter::Y4m(mut file) => {
+                file.flush()?;
+            }
+        }
+
+        return Ok(self.frames_written);
+    }
+}
+
+fn create(path: &str) -> Result<BufWriter<File>, SimulationError> {
+    return Ok(BufWriter::new(File::create(path)?));
+}
+
+// bilinear resampling, so the video size does not have to match the world
+fn resample(view: &RGBCanvas, width: usize, height: usize) -> Vec<u8> {
+    let source_width: usize = view.width;
+    let source_height: usize = view.height;
+
+    if source_width == width && source_height == height {
+        return view.data.clone();
+    }
+
+    let mut pixels: Vec<u8> = vec![0; width * height * 3];
+
+    for y in 0..height {
+        let source_y: f64 = ((y as f64 + 0.5) * source_height as f64 / height as f64 - 0.5).max(0.0);
+        let y0: usize = (source_y as usize).min(source_height - 1);
+        let y1: usize = (y0 + 1).min(source_height - 1);
+        let y_weight: f64 = source_y - y0 as f64;
+
+        for x in 0..width {
+            let source_x: f64 = ((x as f64 + 0.5) * source_width as f64 / width as f64 - 0.5).max(0.0);
+            let x0: usize = (source_x as usize).min(source_width - 1);
+            let x1: usize = (x0 + 1).min(source_width - 1);
+            let x_weight: f64 = source_x - x0 as f64;
+
+            for channel in 0..3 {
+                let at = |x: usize, y: usize| view.data[(y * source_width + x) * 3 + channel] as f64;
+
+                let top: f64 = at(x0, y0) * (1.0 - x_weight) + at(x1, y0) * x_weight;
+                let bottom: f64 = at(x0, y1) * (1.0 - x_weight) + at(x1, y1) * x_weight;
+
+                pixels[(y * width + x) * 3 + channel] = (top * (1.0 - y_weight) + bottom * y_weight).round() as u8;
+            }
+        }
+    }
+
+    return pixels;
+}
+
+// BT.601 studio range, what ffmpeg assumes for y4m input
+fn to_yuv(pixels: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let count: usize = pixels.len() / 3;
+    let mut luma: Vec<u8> = Vec::with_capacity(count);
+    let mut blue: Vec<u8> = Vec::with_capacity(count);
+    let mut red: Vec<u8> = Vec::with_capacity(count);
+
+    for pixel in pixels.chunks_exact(3) {
+        let (r, g, b) = (pixel[0] as f64, pixel[1] as f64, pixel[2] as f64);
+
+        luma.push((16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8);
+        blue.push((128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8);
+        red.push((128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8);
+    }
+
+    return (luma, blue, red);
+}